@@ -1,6 +1,5 @@
 use crate::{
     error::Result,
-    service::Service,
     statics::{self, CF_ZONE_IDENT},
 };
 
@@ -16,11 +15,9 @@ impl Cloudflare {
         &CF_ZONE_IDENT
     }
 
-    pub(crate) async fn purge_cache<T: Service>(
-        client: &Client,
-        file: &str,
-    ) -> Result<HttpResponse> {
-        let payload = CfPurgeRequest::singleton::<T>(file);
+    /// Purges a single path, e.g. `/github/user/repo/commit/file`, from the Cloudflare cache.
+    pub(crate) async fn purge_cache(client: &Client, path: &str) -> Result<HttpResponse> {
+        let payload = CfPurgeRequest::singleton(path);
         trace!("{payload:#?}");
         let response = client
             .post(format!(
@@ -51,13 +48,9 @@ struct CfPurgeRequest {
 }
 
 impl CfPurgeRequest {
-    fn singleton<T: Service>(file: &str) -> Self {
+    fn singleton(path: &str) -> Self {
         Self {
-            files: vec![format!(
-                "https://{}/{}/{file}",
-                &*statics::HOSTNAME,
-                T::path(),
-            )],
+            files: vec![format!("https://{}{path}", &*statics::HOSTNAME)],
         }
     }
 }
@@ -0,0 +1,148 @@
+//! Concatenation of multiple files into a single response.
+
+use crate::{
+    data::{FilePath, State},
+    error::{Error, Result},
+    service::{with_service, AnyService, Service, Services},
+};
+
+use actix_web::{
+    dev::{Path, ResourceDef},
+    web::Bytes,
+};
+use awc::{http::StatusCode, Client};
+use futures_util::future::try_join_all;
+use tracing::error;
+
+use std::sync::Arc;
+
+/// Upper bound for the number of files in a single request
+const MAX_PARTS: usize = 20;
+
+/// A file that is part of a combined response.
+pub(crate) struct Part {
+    pub(crate) data: FilePath,
+    pub(crate) content: Bytes,
+}
+
+/// Fetches all files of a combine request concurrently. `parts` is a comma separated list of
+/// paths, each consisting of the service and the usual file path, e.g.
+/// `github/user/repo/main/a.js,gitlab/user/repo/main/b.js`.
+pub(crate) async fn fetch_parts(
+    services: &Services,
+    client: &Client,
+    cache: &State,
+    parts: &str,
+) -> Result<Vec<Part>> {
+    let parts: Vec<_> = parts.split(',').filter(|part| !part.is_empty()).collect();
+    if parts.is_empty() {
+        return Err(Error::Upstream(StatusCode::NOT_FOUND));
+    }
+    if parts.len() > MAX_PARTS {
+        error!(parts = parts.len(), "too many files to combine");
+        return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    try_join_all(
+        parts
+            .into_iter()
+            .map(|part| fetch_part(services, client, cache, part)),
+    )
+    .await
+}
+
+async fn fetch_part(
+    services: &Services,
+    client: &Client,
+    cache: &State,
+    part: &str,
+) -> Result<Part> {
+    let (name, path) = part
+        .split_once('/')
+        .ok_or(Error::Upstream(StatusCode::NOT_FOUND))?;
+    let service = services
+        .find(name)
+        .ok_or(Error::Upstream(StatusCode::NOT_FOUND))?;
+    with_service!(service, service => fetch(service, client, cache, path).await)
+}
+
+/// Resolves the ref of a single file and fetches it.
+async fn fetch<T: Service>(
+    service: &T,
+    client: &Client,
+    cache: &State,
+    path: &str,
+) -> Result<Part> {
+    let data = parse(service, path).ok_or(Error::Upstream(StatusCode::NOT_FOUND))?;
+    let head = service.head(client, &data, cache).await?;
+    let url = service.raw_url(&data.user, &data.repo, &head.commit, &data.file);
+    let content = service.fetch_raw(client, &url).await?;
+    Ok(Part { data, content })
+}
+
+/// Matches `path` against the file routes of `service`.
+fn parse<T: Service>(service: &T, path: &str) -> Option<FilePath> {
+    let mut path = Path::new(format!("/{path}"));
+    service
+        .file_routes()
+        .all()
+        .any(|pattern| ResourceDef::new(pattern).capture_match_info(&mut path))
+        .then(|| FilePath {
+            user: Arc::new(path.get("user").unwrap_or_default().to_string()),
+            repo: Arc::new(path.get("repo").unwrap_or_default().to_string()),
+            commit: Arc::new(path.get("commit").unwrap_or_default().to_string()),
+            file: Arc::new(path.get("file").unwrap_or_default().to_string()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::service::{Bitbucket, SourceHut};
+
+    #[test]
+    fn parse_file() {
+        let data = parse(&Bitbucket, "user/repo/main/dir/a.js").unwrap();
+        assert_eq!("user", *data.user);
+        assert_eq!("repo", *data.repo);
+        assert_eq!("main", *data.commit);
+        assert_eq!("dir/a.js", *data.file);
+    }
+
+    #[test]
+    fn parse_separated_ref() {
+        let data = parse(&Bitbucket, "user/repo/release/1.2/-/dir/a.js").unwrap();
+        assert_eq!("release/1.2", *data.commit);
+        assert_eq!("dir/a.js", *data.file);
+    }
+
+    #[test]
+    fn parse_separator_after_ref_without_slash() {
+        let data = parse(&Bitbucket, "user/repo/main/-/a.js").unwrap();
+        assert_eq!("main", *data.commit);
+        assert_eq!("-/a.js", *data.file);
+    }
+
+    #[test]
+    fn parse_pinned_before_separated_ref() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let data = parse(&Bitbucket, &format!("user/repo/{commit}/-/a.js")).unwrap();
+        assert_eq!(commit, *data.commit);
+        assert_eq!("a.js", *data.file);
+        let data = parse(&Bitbucket, &format!("user/repo/{commit}/dir/-/a.js")).unwrap();
+        assert_eq!(commit, *data.commit);
+        assert_eq!("dir/-/a.js", *data.file);
+    }
+
+    #[test]
+    fn parse_service_user_pattern() {
+        let data = parse(&SourceHut, "~user/repo/main/a.js").unwrap();
+        assert_eq!("~user", *data.user);
+        assert!(parse(&SourceHut, "user/repo/main/a.js").is_none());
+    }
+
+    #[test]
+    fn parse_incomplete_path() {
+        assert!(parse(&Bitbucket, "user/repo").is_none());
+        assert!(parse(&Bitbucket, "user/repo/main").is_none());
+    }
+}
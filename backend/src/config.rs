@@ -1,6 +1,9 @@
+use crate::error::{Error, Result};
+
 use clap::Parser;
+use serde::Deserialize;
 
-use std::net::IpAddr;
+use std::{collections::HashSet, fs, net::IpAddr, path::Path};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -20,6 +23,12 @@ pub(crate) struct Opt {
     #[arg(long = "gh-secret")]
     /// GitHub OAuth client secret
     pub(crate) github_secret: Option<String>,
+    #[arg(long = "azure-token")]
+    /// Azure DevOps personal access token
+    pub(crate) azure_token: Option<String>,
+    #[arg(long = "hf-token")]
+    /// Hugging Face access token
+    pub(crate) hf_token: Option<String>,
     #[arg(long = "cf-zone")]
     /// Cloudflare zone identifier
     pub(crate) cf_zone: Option<String>,
@@ -32,4 +41,192 @@ pub(crate) struct Opt {
     #[arg(long = "hostname")]
     /// Hostname
     pub(crate) hostname: Option<String>,
+    #[arg(long = "instances")]
+    /// Path to a TOML file describing additional service instances
+    pub(crate) instances: Option<String>,
+}
+
+/// Additional, self-hosted service instances, loaded from a TOML file.
+#[derive(Deserialize, Default)]
+pub(crate) struct Instances {
+    #[serde(default)]
+    pub(crate) instance: Vec<InstanceConfig>,
+}
+
+/// Path prefixes that are already in use and cannot be used as instance names.
+const RESERVED_NAMES: &[&str] = &[
+    "github",
+    "gitlab",
+    "bitbucket",
+    "gitea",
+    "codeberg",
+    "sourcehut",
+    "azure",
+    "huggingface",
+    "gist",
+    "api",
+    "archive",
+    "combine",
+    "favicon.ico",
+];
+
+impl Instances {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses and validates the instances described by `config`.
+    fn parse(config: &str) -> Result<Self> {
+        let instances: Self = toml::from_str(config)?;
+        let mut names = HashSet::new();
+        for instance in &instances.instance {
+            if instance.name.is_empty() || instance.name.contains('/') {
+                return Err(Error::Config(format!(
+                    "invalid instance name: {:?}",
+                    instance.name
+                )));
+            }
+            if RESERVED_NAMES.contains(&instance.name.as_str()) {
+                return Err(Error::Config(format!(
+                    "reserved instance name: {}",
+                    instance.name
+                )));
+            }
+            if !names.insert(&instance.name) {
+                return Err(Error::Config(format!(
+                    "duplicate instance name: {}",
+                    instance.name
+                )));
+            }
+        }
+        Ok(instances)
+    }
+}
+
+/// A single named instance of a known service.
+#[derive(Deserialize, Clone)]
+pub(crate) struct InstanceConfig {
+    /// Name of the instance, used as the first path segment, e.g. `gitlab-corp`
+    pub(crate) name: String,
+    /// Kind of service running on the instance
+    pub(crate) kind: InstanceKind,
+    /// Base URL of the instance, e.g. `https://gitlab.example.com`
+    pub(crate) url: String,
+    /// API base URL, derived from `url` if not set
+    pub(crate) api_url: Option<String>,
+    /// Base URL for raw files, derived from `url` if not set
+    pub(crate) raw_url: Option<String>,
+    /// Access token used for API and raw file requests
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InstanceKind {
+    Github,
+    Gitlab,
+    Gitea,
+    /// Bitbucket Server or Data Center
+    #[serde(rename = "bitbucket-server")]
+    BitbucketServer,
+    /// Gitiles, e.g. `googlesource.com` or Gerrit
+    Gitiles,
+    Cgit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instances;
+    use crate::error::Error;
+
+    #[test]
+    fn parse_instances() {
+        let instances = Instances::parse(
+            r#"
+[[instance]]
+name = "gitlab-corp"
+kind = "gitlab"
+url = "https://gitlab.example.com"
+token = "secret"
+
+[[instance]]
+name = "bitbucket-corp"
+kind = "bitbucket-server"
+url = "https://bitbucket.example.com"
+"#,
+        )
+        .unwrap();
+        let names: Vec<_> = instances
+            .instance
+            .iter()
+            .map(|instance| instance.name.as_str())
+            .collect();
+        assert_eq!(vec!["gitlab-corp", "bitbucket-corp"], names);
+    }
+
+    #[test]
+    fn parse_without_instances() {
+        assert!(Instances::parse("").unwrap().instance.is_empty());
+    }
+
+    #[test]
+    fn reject_reserved_name() {
+        let result = Instances::parse(
+            r#"
+[[instance]]
+name = "github"
+kind = "github"
+url = "https://github.example.com"
+"#,
+        );
+        assert!(
+            matches!(result, Err(Error::Config(msg)) if msg == "reserved instance name: github")
+        );
+    }
+
+    #[test]
+    fn reject_duplicate_name() {
+        let result = Instances::parse(
+            r#"
+[[instance]]
+name = "corp"
+kind = "gitea"
+url = "https://gitea.example.com"
+
+[[instance]]
+name = "corp"
+kind = "cgit"
+url = "https://git.example.com"
+"#,
+        );
+        assert!(
+            matches!(result, Err(Error::Config(msg)) if msg == "duplicate instance name: corp")
+        );
+    }
+
+    #[test]
+    fn reject_invalid_name() {
+        let result = Instances::parse(
+            r#"
+[[instance]]
+name = "corp/git"
+kind = "gitea"
+url = "https://gitea.example.com"
+"#,
+        );
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn reject_unknown_kind() {
+        let result = Instances::parse(
+            r#"
+[[instance]]
+name = "corp"
+kind = "sourceforge"
+url = "https://sourceforge.example.com"
+"#,
+        );
+        assert!(matches!(result, Err(Error::Toml(_))));
+    }
 }
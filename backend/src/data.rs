@@ -1,29 +1,385 @@
-use crate::service;
+use crate::{
+    error::{Error, Result},
+    service,
+    single_flight::SingleFlight,
+    statics::{
+        CACHE_CAPACITY, COMMIT_AGE, DEFAULT_BRANCH_AGE, IMMUTABLE_AGE_SECS, MINIFIED_CAPACITY,
+        MISSING_AGE, REDIRECT_AGE, REDIRECT_AGE_SECS, STALE_AGE, STALE_RETRY_AGE, TAG_AGE,
+        TAG_AGE_SECS, TAG_LIST_AGE,
+    },
+};
 
-use serde::Deserialize;
-use time_cache::Cache;
-use tokio::sync::RwLock;
+use actix_web::{http::StatusCode, web::Bytes, ResponseError};
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use time_cache::{CacheResult, ConcurrentCache};
+use tracing::{debug, error};
 
-use std::sync::Arc;
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-pub(crate) type State = RwLock<Cache<Key, String>>;
+/// Cached ref resolutions. Each kind of value is cached for its own time, see `Cached::ttl`.
+pub(crate) struct State {
+    refs: ConcurrentCache<CacheKey, Cached>,
+    /// Minified files. Only files at full commit hashes are minified, so they never change. They
+    /// are much larger than refs and are therefore bounded separately
+    minified: ConcurrentCache<FileKey, Bytes>,
+    /// Running head lookups. Errors are shared as their status code
+    lookups: SingleFlight<Key, Result<Head, StatusCode>>,
+}
 
-#[derive(Deserialize, Debug)]
+impl State {
+    pub(crate) fn new() -> Self {
+        Self {
+            refs: ConcurrentCache::bounded(REDIRECT_AGE, CACHE_CAPACITY)
+                .ttl_from_value(Cached::ttl)
+                .grace_period(STALE_AGE),
+            minified: ConcurrentCache::bounded(COMMIT_AGE, MINIFIED_CAPACITY),
+            lookups: SingleFlight::new(),
+        }
+    }
+
+    /// Loads a value from `cache`. Invalid elements are removed from the cache, stale ones are
+    /// ignored.
+    fn lookup<K: Eq + Hash, V: Clone>(cache: &ConcurrentCache<K, V>, key: &K) -> Option<V> {
+        match cache.get(key) {
+            CacheResult::Cached(value) => Some(value),
+            CacheResult::Invalid => {
+                debug!("Removing invalid element from cache");
+                cache.invalidate(key);
+                None
+            }
+            CacheResult::Stale(_) | CacheResult::Empty => None,
+        }
+    }
+
+    /// Loads a cached head. Refs that are known not to exist result in an error.
+    pub(crate) fn get(&self, key: &Key) -> Option<Result<Head>> {
+        match Self::lookup(&self.refs, &CacheKey::Head(key.clone()))? {
+            Cached::Head(head, _) => Some(Ok(head)),
+            // stale heads are refreshed like uncached ones
+            Cached::Refreshing(..) => None,
+            _ => Some(Err(Error::Upstream(StatusCode::NOT_FOUND))),
+        }
+    }
+
+    /// Loads a cached head that expired less than `STALE_AGE` ago and whether it must be
+    /// refreshed. Returns `None` for valid heads. Only the first caller is told to refresh the
+    /// head, later ones get the stale head without refreshing it for `STALE_RETRY_AGE`, even if
+    /// the refresh fails.
+    pub(crate) fn stale(&self, key: &Key) -> Option<(Head, bool)> {
+        let key = CacheKey::Head(key.clone());
+        let now = Instant::now();
+        let (head, stale_until) = match self.refs.get(&key) {
+            CacheResult::Stale(Cached::Head(head, resolved)) => {
+                let stale_until = resolved + Cached::head_ttl(head.kind) + STALE_AGE;
+                (head, stale_until)
+            }
+            CacheResult::Cached(Cached::Refreshing(head, stale_until)) if now < stale_until => {
+                return Some((head, false));
+            }
+            CacheResult::Stale(Cached::Refreshing(head, stale_until)) => (head, stale_until),
+            _ => return None,
+        };
+        if now >= stale_until {
+            return None;
+        }
+        // re-storing the head would extend the grace period, so the deadline is kept
+        self.refs
+            .store(key, Cached::Refreshing(head.clone(), stale_until));
+        Some((head, true))
+    }
+
+    /// Loads the cached name of a repository's default branch.
+    pub(crate) fn default_branch(&self, key: &Key) -> Option<String> {
+        match Self::lookup(&self.refs, &CacheKey::DefaultBranch(key.clone()))? {
+            Cached::DefaultBranch(branch) => Some(branch),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn store_default_branch(&self, key: Key, branch: String) {
+        self.refs
+            .store(CacheKey::DefaultBranch(key), Cached::DefaultBranch(branch));
+    }
+
+    /// Loads the cached tags of a repository.
+    pub(crate) fn tags(&self, key: &RepoKey) -> Option<Arc<Vec<Tag>>> {
+        match Self::lookup(&self.refs, &CacheKey::Tags(key.clone()))? {
+            Cached::Tags(tags) => Some(tags),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn store_tags(&self, key: RepoKey, tags: Arc<Vec<Tag>>) {
+        self.refs.store(CacheKey::Tags(key), Cached::Tags(tags));
+    }
+
+    /// Loads the cached `(path, url)` pairs of the submodules at a commit.
+    pub(crate) fn submodules(&self, key: &Key) -> Option<Arc<Vec<(String, String)>>> {
+        match Self::lookup(&self.refs, &CacheKey::Submodules(key.clone()))? {
+            Cached::Submodules(submodules) => Some(submodules),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn store_submodules(&self, key: Key, submodules: Arc<Vec<(String, String)>>) {
+        self.refs
+            .store(CacheKey::Submodules(key), Cached::Submodules(submodules));
+    }
+
+    /// Loads a cached minified file.
+    pub(crate) fn minified(&self, key: &FileKey) -> Option<Bytes> {
+        Self::lookup(&self.minified, key)
+    }
+
+    pub(crate) fn store_minified(&self, key: FileKey, content: Bytes) {
+        self.minified.store(key, content);
+    }
+
+    pub(crate) fn store(&self, key: Key, head: &Head) {
+        self.refs.store(
+            CacheKey::Head(key),
+            Cached::Head(head.clone(), Instant::now()),
+        );
+    }
+
+    /// Remembers that the requested ref does not exist.
+    pub(crate) fn store_missing(&self, key: Key) {
+        self.refs.store(CacheKey::Head(key), Cached::Missing);
+    }
+
+    /// Runs `lookup` for the head of `key`, unless a lookup for the same key is already running.
+    /// In that case, the result of the running lookup is used.
+    pub(crate) async fn lookup_head(
+        &self,
+        key: &Key,
+        lookup: impl Future<Output = Result<Head>>,
+    ) -> Result<Head> {
+        self.lookups
+            .run(key, async {
+                lookup.await.map_err(|e| {
+                    error!(error = %e, "failed to resolve head");
+                    e.status_code()
+                })
+            })
+            .await
+            .map_err(Error::Upstream)
+    }
+
+    /// Removes `key` from the cache. Returns `true` if the key was present.
+    pub(crate) fn invalidate(&self, key: &Key) -> bool {
+        let head = self.refs.invalidate(&CacheKey::Head(key.clone()));
+        let default_branch = self.refs.invalidate(&CacheKey::DefaultBranch(key.clone()));
+        head || default_branch
+    }
+}
+
+/// Key of a cached value. The `HEAD` alias is cached both as a head and as the name of the
+/// default branch.
+#[derive(Eq, PartialEq, Hash)]
+enum CacheKey {
+    Head(Key),
+    DefaultBranch(Key),
+    Tags(RepoKey),
+    Submodules(Key),
+}
+
+#[derive(Clone)]
+enum Cached {
+    /// Head and the time it was resolved
+    Head(Head, Instant),
+    /// Expired head that is being refreshed and the time it is no longer served
+    Refreshing(Head, Instant),
+    /// The requested ref does not exist
+    Missing,
+    DefaultBranch(String),
+    /// Tags of a repository, used to resolve version ranges
+    Tags(Arc<Vec<Tag>>),
+    /// Submodules declared in `.gitmodules` at a full commit hash
+    Submodules(Arc<Vec<(String, String)>>),
+}
+
+impl Cached {
+    /// Time the value is cached for. Tags rarely move, so they are cached longer than branches.
+    /// Expanded commit hashes never change and are cached the longest.
+    fn ttl(&self) -> Duration {
+        match self {
+            Cached::Head(head, _) => Self::head_ttl(head.kind),
+            Cached::Refreshing(..) => STALE_RETRY_AGE,
+            Cached::Missing => MISSING_AGE,
+            Cached::DefaultBranch(_) => DEFAULT_BRANCH_AGE,
+            Cached::Tags(_) => TAG_LIST_AGE,
+            Cached::Submodules(_) => COMMIT_AGE,
+        }
+    }
+
+    fn head_ttl(kind: RefKind) -> Duration {
+        match kind {
+            RefKind::Branch => REDIRECT_AGE,
+            RefKind::Tag => TAG_AGE,
+            RefKind::Commit => COMMIT_AGE,
+        }
+    }
+}
+
+/// Kind of ref a requested name was resolved from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RefKind {
+    Branch,
+    Tag,
+    /// Abbreviated commit hash
+    Commit,
+}
+
+/// Commit a ref points to.
+#[derive(Clone, Debug)]
+pub(crate) struct Head {
+    pub(crate) commit: String,
+    pub(crate) kind: RefKind,
+}
+
+impl Head {
+    pub(crate) fn new(commit: String, kind: RefKind) -> Self {
+        Self { commit, kind }
+    }
+
+    /// Time in seconds, redirects to the commit can be cached.
+    pub(crate) fn max_age(&self) -> u32 {
+        match self.kind {
+            RefKind::Branch => *REDIRECT_AGE_SECS,
+            RefKind::Tag => *TAG_AGE_SECS,
+            RefKind::Commit => IMMUTABLE_AGE_SECS,
+        }
+    }
+}
+
+/// A tag and the commit it points to. Annotated tags are peeled to the tagged commit.
+#[derive(Debug)]
+pub(crate) struct Tag {
+    pub(crate) name: String,
+    pub(crate) commit: String,
+}
+
+impl Tag {
+    pub(crate) fn new(name: String, commit: String) -> Self {
+        Self { name, commit }
+    }
+}
+
+/// Entry of a directory listing.
+#[derive(Serialize, Debug)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    /// Size in bytes, not reported by every service
+    pub(crate) size: Option<u64>,
+    #[serde(rename = "type")]
+    pub(crate) kind: EntryKind,
+    /// Hash of the git object, not reported by every service
+    pub(crate) sha: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Submodule,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub(crate) struct FilePath {
+    /// User or organization owning the repository. Might carry a service specific prefix, like
+    /// `~` for `SourceHut`, or span multiple segments, like `organization/project` for Azure DevOps
     pub(crate) user: Arc<String>,
     pub(crate) repo: Arc<String>,
     pub(crate) commit: Arc<String>,
+    /// Requested file or, for listings, directory. Empty for the root directory
+    #[serde(default)]
     pub(crate) file: Arc<String>,
 }
 
 impl FilePath {
+    /// Reserved ref that resolves to the repository's default branch
+    pub(crate) const DEFAULT_BRANCH: &'static str = "HEAD";
+
+    /// Checks if the requested ref is the default branch alias.
+    pub(crate) fn is_default_branch(&self) -> bool {
+        *self.commit == Self::DEFAULT_BRANCH
+    }
+
+    /// Returns a copy of the path, pointing to another ref.
+    pub(crate) fn with_commit(&self, commit: String) -> Self {
+        Self {
+            commit: Arc::new(commit),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the path, pointing to another file.
+    pub(crate) fn with_file(&self, file: String) -> Self {
+        Self {
+            file: Arc::new(file),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the path, with a ref that was terminated by `/-/` split at its first
+    /// slash. The rest of the ref becomes part of the file, e.g. `main/docs/-/x.md` is turned into
+    /// the file `docs/-/x.md` on `main`. Returns `None` for refs without slashes.
+    pub(crate) fn unseparated(&self) -> Option<Self> {
+        let (commit, dir) = self.commit.split_once('/')?;
+        Some(Self {
+            commit: Arc::new(commit.to_string()),
+            file: Arc::new(format!("{dir}/-/{}", self.file)),
+            ..self.clone()
+        })
+    }
+
     pub(crate) fn path(&self) -> String {
         format!("{}/{}/{}/{}", self.user, self.repo, self.commit, self.file)
     }
 
-    pub(crate) fn to_key<T: service::Service>(&self) -> Key {
+    /// Checks if the requested ref is a full commit hash.
+    pub(crate) fn is_full_commit(&self) -> bool {
+        self.commit.len() == 40 && self.commit.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    /// Checks if the requested ref looks like an abbreviated commit hash.
+    pub(crate) fn is_short_commit(&self) -> bool {
+        (7..40).contains(&self.commit.len()) && self.commit.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    /// Parses the requested ref as a semver range, like `^1.2` or `~1.2.3`. Only refs starting
+    /// with an operator are considered ranges, so tags like `1.2.3` are still resolved exactly.
+    pub(crate) fn version_range(&self) -> Option<VersionReq> {
+        if self.commit.starts_with(['^', '~', '=', '<', '>', '*']) {
+            VersionReq::parse(&self.commit).ok()
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn to_repo_key<T: service::Service>(&self, service: &T) -> RepoKey {
+        RepoKey(
+            service.cache_service(),
+            Arc::clone(&self.user),
+            Arc::clone(&self.repo),
+        )
+    }
+
+    pub(crate) fn to_file_key<T: service::Service>(&self, service: &T) -> FileKey {
+        FileKey(self.to_key(service), Arc::clone(&self.file))
+    }
+
+    pub(crate) fn to_key<T: service::Service>(&self, service: &T) -> Key {
         Key::new(
-            T::cache_service(),
+            service.cache_service(),
             Arc::clone(&self.user),
             Arc::clone(&self.repo),
             Arc::clone(&self.commit),
@@ -31,14 +387,69 @@ impl FilePath {
     }
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
+/// Query of a file request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct FileQuery {
+    /// Minifies JavaScript and CSS files if set, regardless of the value
+    pub(crate) minify: Option<String>,
+}
+
+/// Query of an archive request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ArchiveQuery {
+    /// Restricts the archive to a subdirectory
+    pub(crate) path: Option<String>,
+}
+
+/// Path of a GitHub release asset.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ReleasePath {
+    pub(crate) user: Arc<String>,
+    pub(crate) repo: Arc<String>,
+    pub(crate) tag: Arc<String>,
+    pub(crate) asset: Arc<String>,
+}
+
+impl ReleasePath {
+    /// Alias for the most recent release
+    pub(crate) const LATEST: &'static str = "latest";
+
+    pub(crate) fn path(&self) -> String {
+        format!(
+            "{}/{}/releases/{}/{}",
+            self.user, self.repo, self.tag, self.asset
+        )
+    }
+
+    pub(crate) fn is_latest(&self) -> bool {
+        *self.tag == Self::LATEST
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub(crate) struct Key(Service, Arc<String>, Arc<String>, Arc<String>);
 
+/// Like `Key` but identifies a single file at a ref.
 #[derive(Eq, PartialEq, Hash, Debug)]
+pub(crate) struct FileKey(Key, Arc<String>);
+
+/// Like `Key` but identifies a whole repository instead of a ref.
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
+pub(crate) struct RepoKey(Service, Arc<String>, Arc<String>);
+
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub(crate) enum Service {
     GitHub,
+    Gist,
     GitLab,
     Bitbucket,
+    Gitea,
+    Codeberg,
+    SourceHut,
+    Azure,
+    HuggingFace,
+    /// A configured instance, identified by its name
+    Instance(Arc<String>),
 }
 
 impl Key {
@@ -51,3 +462,120 @@ impl Key {
         Key(service, user, repo, branch)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheKey, Cached, FilePath, Head, Key, RefKind, Service, State};
+    use semver::Version;
+    use time_cache::CacheResult;
+
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    fn key() -> Key {
+        Key::new(
+            Service::GitHub,
+            Arc::new("user".to_string()),
+            Arc::new("repo".to_string()),
+            Arc::new("main".to_string()),
+        )
+    }
+
+    fn head() -> Head {
+        Head::new("0".repeat(40), RefKind::Branch)
+    }
+
+    #[test]
+    fn stale_head_is_refreshed_once() {
+        let state = State::new();
+        let cached = Cached::Head(head(), Instant::now());
+        state
+            .refs
+            .store_with_ttl(CacheKey::Head(key()), cached, Duration::ZERO);
+        assert!(matches!(state.stale(&key()), Some((_, true))));
+        assert!(matches!(state.stale(&key()), Some((_, false))));
+        assert!(state.get(&key()).is_none());
+    }
+
+    #[test]
+    fn failed_refreshes_keep_stale_deadline() {
+        let state = State::new();
+        let stale_until = Instant::now() + Duration::from_secs(60);
+        let cached = Cached::Refreshing(head(), stale_until);
+        state
+            .refs
+            .store_with_ttl(CacheKey::Head(key()), cached, Duration::ZERO);
+        assert!(matches!(state.stale(&key()), Some((_, true))));
+        assert!(matches!(
+            state.refs.get(&CacheKey::Head(key())),
+            CacheResult::Cached(Cached::Refreshing(_, until)) if until == stale_until
+        ));
+    }
+
+    #[test]
+    fn stale_head_is_dropped_after_stale_age() {
+        let state = State::new();
+        let cached = Cached::Refreshing(head(), Instant::now());
+        state
+            .refs
+            .store_with_ttl(CacheKey::Head(key()), cached, Duration::ZERO);
+        assert!(state.stale(&key()).is_none());
+    }
+
+    fn path(commit: &str) -> FilePath {
+        FilePath {
+            user: Arc::new("user".to_string()),
+            repo: Arc::new("repo".to_string()),
+            commit: Arc::new(commit.to_string()),
+            file: Arc::new("README.md".to_string()),
+        }
+    }
+
+    #[test]
+    fn short_commit() {
+        assert!(path("1234abc").is_short_commit());
+        assert!(path("1234567890ABCDEF").is_short_commit());
+        assert!(path(&"a".repeat(39)).is_short_commit());
+    }
+
+    #[test]
+    fn not_short_commit() {
+        assert!(!path("123456").is_short_commit());
+        assert!(!path(&"a".repeat(40)).is_short_commit());
+        assert!(!path("deadbeefx").is_short_commit());
+        assert!(!path("master").is_short_commit());
+    }
+
+    #[test]
+    fn unseparated() {
+        let data = path("main/docs").unseparated().unwrap();
+        assert_eq!("main", *data.commit);
+        assert_eq!("docs/-/README.md", *data.file);
+        let data = path("release/1.2/docs").unseparated().unwrap();
+        assert_eq!("release", *data.commit);
+        assert_eq!("1.2/docs/-/README.md", *data.file);
+        assert!(path("main").unseparated().is_none());
+    }
+
+    #[test]
+    fn version_range() {
+        let range = path("^1.2").version_range().unwrap();
+        assert!(range.matches(&Version::new(1, 4, 0)));
+        assert!(!range.matches(&Version::new(2, 0, 0)));
+        let range = path("~1.2.3").version_range().unwrap();
+        assert!(range.matches(&Version::new(1, 2, 9)));
+        assert!(!range.matches(&Version::new(1, 3, 0)));
+        assert!(path(">=1.0, <2").version_range().is_some());
+        assert!(path("*").version_range().is_some());
+    }
+
+    #[test]
+    fn exact_refs_are_no_version_range() {
+        assert!(path("1.2.3").version_range().is_none());
+        assert!(path("v1.2.3").version_range().is_none());
+        assert!(path("master").version_range().is_none());
+        assert!(path("^not-a-version").version_range().is_none());
+    }
+}
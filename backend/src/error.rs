@@ -1,4 +1,4 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -12,12 +12,36 @@ pub(crate) enum Error {
     HttpServer(#[from] actix_web::Error),
     #[error("Io({0})")]
     Io(#[from] std::io::Error),
+    #[error("Config({0})")]
+    Config(String),
+    #[error("Toml({0})")]
+    Toml(#[from] toml::de::Error),
     #[error("Json({0})")]
     Json(#[from] awc::error::JsonPayloadError),
+    #[error("SerdeJson({0})")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("Base64({0})")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Minify({0})")]
+    Minify(&'static str),
+    #[error("Upstream({0})")]
+    Upstream(awc::http::StatusCode),
 }
 
 impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // forward the status code of failed requests against a service
+            Error::Upstream(code) => *code,
+            // buffered upstream responses exceeded `service::MAX_BODY_SIZE`
+            Error::HttpPayload(awc::error::PayloadError::Overflow) => StatusCode::PAYLOAD_TOO_LARGE,
+            // the requested file cannot be minified
+            Error::Minify(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::InternalServerError().finish()
+        HttpResponse::build(self.status_code()).finish()
     }
 }
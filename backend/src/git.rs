@@ -0,0 +1,358 @@
+//! Helpers for the git smart HTTP protocol.
+
+use crate::data::Tag;
+
+use std::collections::HashMap;
+
+/// Parses the ref advertisement returned by `info/refs?service=git-upload-pack` and yields
+/// `(commit, ref name)` pairs. Peeled tags are advertised with a `^{}` suffix.
+pub(crate) fn advertised_refs(body: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    PktLines(body).filter_map(|line| {
+        let line = std::str::from_utf8(line).ok()?;
+        // the first ref is followed by the server capabilities
+        let line = line.split('\0').next()?.trim_end_matches('\n');
+        let (commit, name) = line.split_once(' ')?;
+        if commit.len() == 40 && commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Some((commit, name))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parses the `info/refs` file served to dumb HTTP clients and yields `(commit, ref name)` pairs.
+pub(crate) fn dumb_refs(body: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    body.split(|&b| b == b'\n').filter_map(|line| {
+        let line = std::str::from_utf8(line).ok()?;
+        line.split_once('\t')
+    })
+}
+
+/// Parses the `HEAD` file served to dumb HTTP clients, e.g. `ref: refs/heads/master`, and
+/// returns the branch it points to.
+pub(crate) fn dumb_head(body: &[u8]) -> Option<&str> {
+    std::str::from_utf8(body)
+        .ok()?
+        .trim()
+        .strip_prefix("ref: refs/heads/")
+}
+
+/// Collects the tags of a ref advertisement. Annotated tags are peeled to the tagged commit.
+pub(crate) fn advertised_tags(body: &[u8]) -> Vec<Tag> {
+    tags(advertised_refs(body))
+}
+
+/// Collects the tags of `(commit, ref name)` pairs. Annotated tags are peeled to the tagged
+/// commit.
+pub(crate) fn tags<'a>(refs: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<Tag> {
+    let mut tags = HashMap::new();
+    for (commit, name) in refs {
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        if let Some(tag) = tag.strip_suffix("^{}") {
+            tags.insert(tag, commit);
+        } else {
+            tags.entry(tag).or_insert(commit);
+        }
+    }
+    tags.into_iter()
+        .map(|(name, commit)| Tag::new(name.to_string(), commit.to_string()))
+        .collect()
+}
+
+/// Extracts the branch `HEAD` points to from the capabilities of a ref advertisement, e.g.
+/// `symref=HEAD:refs/heads/master`.
+pub(crate) fn head_symref(body: &[u8]) -> Option<&str> {
+    PktLines(body).find_map(|line| {
+        let line = std::str::from_utf8(line).ok()?;
+        let (_, capabilities) = line.split_once('\0')?;
+        capabilities
+            .split_whitespace()
+            .find_map(|cap| cap.strip_prefix("symref=HEAD:refs/heads/"))
+    })
+}
+
+/// Parses a `.gitmodules` file and returns the `(path, url)` pairs of the submodules.
+pub(crate) fn submodules(config: &str) -> Vec<(&str, &str)> {
+    let mut submodules = Vec::new();
+    let (mut path, mut url) = (None, None);
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            // a new section starts
+            if let (Some(path), Some(url)) = (path.take(), url.take()) {
+                submodules.push((path, url));
+            }
+        } else if let Some((key, value)) = line.split_once('=') {
+            match key.trim() {
+                "path" => path = Some(value.trim()),
+                "url" => url = Some(value.trim()),
+                _ => {}
+            }
+        }
+    }
+    if let (Some(path), Some(url)) = (path, url) {
+        submodules.push((path, url));
+    }
+    submodules
+}
+
+/// Turns a git URL, like `git@github.com:user/repo.git` or `ssh://git@host:22/user/repo`, into
+/// an HTTPS URL without credentials or port. Relative URLs, like `../repo.git`, are resolved
+/// against `base`, the URL of the repository containing the submodule.
+pub(crate) fn normalize_url(url: &str, base: &str) -> Option<String> {
+    let url = url.trim().trim_end_matches('/');
+    if url.starts_with("./") || url.starts_with("../") {
+        let mut resolved = base.trim_end_matches('/').to_string();
+        for segment in url.split('/') {
+            match segment {
+                "." => {}
+                ".." => resolved.truncate(resolved.rfind('/')?),
+                segment => {
+                    resolved.push('/');
+                    resolved.push_str(segment);
+                }
+            }
+        }
+        return Some(resolved);
+    }
+    let (authority, path) = if let Some((_, rest)) = url.split_once("://") {
+        rest.split_once('/')?
+    } else {
+        // scp-like syntax
+        url.split_once(':')?
+    };
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    Some(format!("https://{host}/{path}"))
+}
+
+/// Iterator over the payloads of a sequence of pkt-lines. Flush packets are skipped.
+struct PktLines<'a>(&'a [u8]);
+
+impl<'a> Iterator for PktLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let len = self.0.get(..4)?;
+            let len = usize::from_str_radix(std::str::from_utf8(len).ok()?, 16).ok()?;
+            if len < 4 {
+                // flush and delimiter packets carry no payload
+                self.0 = &self.0[4..];
+                continue;
+            }
+            let line = self.0.get(4..len)?;
+            self.0 = &self.0[len..];
+            return Some(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        advertised_refs, advertised_tags, dumb_head, dumb_refs, head_symref, normalize_url,
+        submodules, PktLines,
+    };
+    use crate::service::{Bitbucket, Service};
+
+    const MASTER: &str = "1111111111111111111111111111111111111111";
+    const TAG: &str = "2222222222222222222222222222222222222222";
+    const TAGGED: &str = "3333333333333333333333333333333333333333";
+
+    /// Wraps `payload` in a pkt-line.
+    fn pkt_line(payload: &str) -> String {
+        format!("{:04x}{payload}", payload.len() + 4)
+    }
+
+    fn advertisement() -> String {
+        [
+            pkt_line("# service=git-upload-pack\n"),
+            "0000".to_string(),
+            pkt_line(&format!(
+                "{MASTER} HEAD\0multi_ack symref=HEAD:refs/heads/master agent=git/2.40\n"
+            )),
+            pkt_line(&format!("{MASTER} refs/heads/master\n")),
+            pkt_line(&format!("{TAG} refs/tags/v1.0.0\n")),
+            pkt_line(&format!("{TAGGED} refs/tags/v1.0.0^{{}}\n")),
+            pkt_line(&format!("{MASTER} refs/tags/v1.1.0\n")),
+            "0000".to_string(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn pkt_lines() {
+        let body = format!("{}0000{}", pkt_line("a"), pkt_line("bc"));
+        assert_eq!(
+            vec![&b"a"[..], &b"bc"[..]],
+            PktLines(body.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn pkt_lines_with_malformed_length() {
+        assert_eq!(None, PktLines(b"zzzzabc").next());
+        // the length exceeds the body
+        assert_eq!(None, PktLines(b"0010abc").next());
+        assert_eq!(None, PktLines(b"00").next());
+        let body = format!("{}00ff", pkt_line("a"));
+        assert_eq!(
+            vec![&b"a"[..]],
+            PktLines(body.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_advertised_refs() {
+        let body = advertisement();
+        assert_eq!(
+            vec![
+                (MASTER, "HEAD"),
+                (MASTER, "refs/heads/master"),
+                (TAG, "refs/tags/v1.0.0"),
+                (TAGGED, "refs/tags/v1.0.0^{}"),
+                (MASTER, "refs/tags/v1.1.0"),
+            ],
+            advertised_refs(body.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn advertised_refs_skip_invalid_commits() {
+        let body = pkt_line("1234 refs/heads/master\n");
+        assert_eq!(0, advertised_refs(body.as_bytes()).count());
+    }
+
+    #[test]
+    fn parse_head_symref() {
+        assert_eq!(Some("master"), head_symref(advertisement().as_bytes()));
+        let body = pkt_line(&format!("{MASTER} HEAD\0multi_ack\n"));
+        assert_eq!(None, head_symref(body.as_bytes()));
+    }
+
+    #[test]
+    fn peel_advertised_tags() {
+        let mut tags = advertised_tags(advertisement().as_bytes())
+            .into_iter()
+            .map(|tag| (tag.name, tag.commit))
+            .collect::<Vec<_>>();
+        tags.sort();
+        assert_eq!(
+            vec![
+                ("v1.0.0".to_string(), TAGGED.to_string()),
+                ("v1.1.0".to_string(), MASTER.to_string()),
+            ],
+            tags
+        );
+    }
+
+    #[test]
+    fn parse_dumb_refs() {
+        let body = format!(
+            "{MASTER}\trefs/heads/master\n{TAG}\trefs/tags/v1.0.0\n{TAGGED}\trefs/tags/v1.0.0^{{}}\n"
+        );
+        assert_eq!(
+            vec![
+                (MASTER, "refs/heads/master"),
+                (TAG, "refs/tags/v1.0.0"),
+                (TAGGED, "refs/tags/v1.0.0^{}"),
+            ],
+            dumb_refs(body.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_dumb_head() {
+        assert_eq!(Some("main"), dumb_head(b"ref: refs/heads/main\n"));
+        assert_eq!(None, dumb_head(MASTER.as_bytes()));
+    }
+
+    const BASE: &str = "https://github.com/user/repo";
+
+    #[test]
+    fn parse_submodules() {
+        let config = r#"
+[submodule "lib"]
+	path = lib
+	url = https://github.com/user/lib.git
+[submodule "vendor/nested"]
+	url = git@github.com:other/nested.git
+	path = vendor/nested
+	branch = main
+[submodule "incomplete"]
+	path = incomplete
+"#;
+        assert_eq!(
+            vec![
+                ("lib", "https://github.com/user/lib.git"),
+                ("vendor/nested", "git@github.com:other/nested.git"),
+            ],
+            submodules(config)
+        );
+    }
+
+    #[test]
+    fn parse_empty_submodules() {
+        assert!(submodules("").is_empty());
+    }
+
+    #[test]
+    fn normalize_https_url() {
+        assert_eq!(
+            Some("https://github.com/user/lib.git".to_string()),
+            normalize_url("https://github.com/user/lib.git", BASE)
+        );
+        assert_eq!(
+            Some("https://github.com/user/lib".to_string()),
+            normalize_url("https://token@github.com/user/lib/", BASE)
+        );
+    }
+
+    #[test]
+    fn normalize_scp_url() {
+        assert_eq!(
+            Some("https://github.com/user/lib.git".to_string()),
+            normalize_url("git@github.com:user/lib.git", BASE)
+        );
+    }
+
+    #[test]
+    fn normalize_ssh_url() {
+        assert_eq!(
+            Some("https://gitlab.com/group/lib".to_string()),
+            normalize_url("ssh://git@gitlab.com:22/group/lib", BASE)
+        );
+    }
+
+    #[test]
+    fn normalize_relative_url() {
+        assert_eq!(
+            Some("https://github.com/user/lib.git".to_string()),
+            normalize_url("../lib.git", BASE)
+        );
+        assert_eq!(
+            Some("https://github.com/other/lib".to_string()),
+            normalize_url("../../other/lib", BASE)
+        );
+        assert_eq!(
+            Some("https://github.com/user/repo/lib".to_string()),
+            normalize_url("./lib", BASE)
+        );
+    }
+
+    #[test]
+    fn normalize_url_without_path() {
+        assert_eq!(None, normalize_url("lib", BASE));
+    }
+
+    #[test]
+    fn git_suffix_is_stripped_from_repo() {
+        let url = normalize_url("git@bitbucket.org:user/lib.git", BASE).unwrap();
+        assert_eq!(
+            Some(("user".to_string(), "lib".to_string())),
+            Bitbucket.parse_repo_url(&url)
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Helpers for Git LFS pointer files and the LFS batch API.
+
+use actix_web::{
+    http::header::{self, HeaderMap},
+    web::{Bytes, BytesMut},
+};
+use awc::error::PayloadError;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+/// Pointer files are always smaller than this
+const MAX_POINTER_SIZE: usize = 1024;
+/// Media type of LFS batch API requests and responses
+pub(crate) const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Pointer to an object in LFS storage, committed in place of the actual file.
+#[derive(Debug, Serialize)]
+pub(crate) struct Pointer {
+    pub(crate) oid: String,
+    pub(crate) size: u64,
+}
+
+impl Pointer {
+    /// Parses a pointer file. Returns `None` if `body` is a regular file.
+    pub(crate) fn parse(body: &[u8]) -> Option<Self> {
+        let body = std::str::from_utf8(body).ok()?;
+        let mut lines = body.lines();
+        if lines.next()? != POINTER_VERSION {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line.split_once(' ')? {
+                ("oid", value) => oid = Some(value.strip_prefix("sha256:")?.to_string()),
+                ("size", value) => size = Some(value.parse().ok()?),
+                _ => {}
+            }
+        }
+        Some(Self {
+            oid: oid?,
+            size: size?,
+        })
+    }
+}
+
+/// Request to download a single object via the `basic` transfer adapter.
+#[derive(Serialize)]
+pub(crate) struct BatchRequest<'a> {
+    operation: &'static str,
+    transfers: [&'static str; 1],
+    objects: [&'a Pointer; 1],
+}
+
+impl<'a> BatchRequest<'a> {
+    pub(crate) fn download(pointer: &'a Pointer) -> Self {
+        Self {
+            operation: "download",
+            transfers: ["basic"],
+            objects: [pointer],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BatchResponse {
+    pub(crate) objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BatchObject {
+    pub(crate) actions: Option<Actions>,
+    /// Set if the object cannot be downloaded, e.g. because it does not exist
+    pub(crate) error: Option<ObjectError>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Actions {
+    pub(crate) download: Action,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Action {
+    pub(crate) href: String,
+    /// Headers to send along with the download, e.g. for authorization
+    #[serde(default)]
+    pub(crate) header: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ObjectError {
+    pub(crate) code: u16,
+}
+
+/// Checks the `Content-Length` of a response for a size that fits a pointer file. Responses
+/// without a known length are never considered pointers, so they can be streamed.
+pub(crate) fn might_be_pointer(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|len| len < MAX_POINTER_SIZE)
+}
+
+/// Reads the start of `body`, until it ends or is too large to be a pointer file. The length
+/// checked by `might_be_pointer` might be compressed, so the body is read in chunks instead of
+/// being buffered. Returns the bytes read and whether the body has ended.
+pub(crate) async fn read_pointer_candidate<S>(body: &mut S) -> Result<(Bytes, bool), PayloadError>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    let mut head = BytesMut::new();
+    while head.len() < MAX_POINTER_SIZE {
+        match body.next().await {
+            Some(chunk) => head.extend_from_slice(&chunk?),
+            None => return Ok((head.freeze(), true)),
+        }
+    }
+    Ok((head.freeze(), false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_pointer_candidate, Pointer, MAX_POINTER_SIZE};
+    use actix_web::web::Bytes;
+    use awc::error::PayloadError;
+    use futures_util::stream;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    #[test]
+    fn parse_pointer() {
+        let body =
+            format!("version https://git-lfs.github.com/spec/v1\noid sha256:{OID}\nsize 12345\n");
+        let pointer = Pointer::parse(body.as_bytes()).unwrap();
+        assert_eq!(OID, pointer.oid);
+        assert_eq!(12345, pointer.size);
+    }
+
+    #[test]
+    fn parse_pointer_without_oid() {
+        let body = "version https://git-lfs.github.com/spec/v1\nsize 12345\n";
+        assert!(Pointer::parse(body.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn parse_pointer_with_wrong_version() {
+        let body = format!("version https://example.com/spec/v2\noid sha256:{OID}\nsize 1\n");
+        assert!(Pointer::parse(body.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn parse_pointer_with_extra_line() {
+        // unknown keys, like extensions, are ignored
+        let body = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{OID}\noid sha256:{OID}\nsize 1\n"
+        );
+        assert!(Pointer::parse(body.as_bytes()).is_some());
+        let body = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{OID}\nsize 1\nextra\n"
+        );
+        assert!(Pointer::parse(body.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn parse_regular_file() {
+        assert!(Pointer::parse(b"# README\n").is_none());
+        assert!(Pointer::parse(&[0xff, 0xfe]).is_none());
+    }
+
+    #[actix_web::test]
+    async fn read_small_body() {
+        let chunks = [Bytes::from_static(b"version "), Bytes::from_static(b"1\n")];
+        let mut body = stream::iter(chunks.map(Ok::<_, PayloadError>));
+        let (head, complete) = read_pointer_candidate(&mut body).await.unwrap();
+        assert_eq!(&b"version 1\n"[..], head);
+        assert!(complete);
+    }
+
+    #[actix_web::test]
+    async fn stop_reading_large_body() {
+        let chunk = Bytes::from(vec![b'a'; MAX_POINTER_SIZE / 2]);
+        let mut body = stream::iter(vec![chunk; 4].into_iter().map(Ok::<_, PayloadError>));
+        let (head, complete) = read_pointer_candidate(&mut body).await.unwrap();
+        assert_eq!(MAX_POINTER_SIZE, head.len());
+        assert!(!complete);
+        assert_eq!(2, stream::StreamExt::count(body).await);
+    }
+}
@@ -1,129 +1,349 @@
 mod cdn;
+mod combine;
 mod config;
 mod data;
 mod error;
+mod git;
+mod lfs;
+mod minify;
 mod service;
+mod single_flight;
 mod statics;
+mod submodule;
 
 use crate::{
     cdn::Cloudflare,
-    data::{FilePath, State},
-    error::Result,
-    service::{Bitbucket, GitLab, Github, Service},
-    statics::{FAVICON, OPT, REDIRECT_AGE, REDIRECT_AGE_SECS},
+    config::Instances,
+    data::{ArchiveQuery, FilePath, FileQuery, ReleasePath, State},
+    error::{Error, Result},
+    lfs::Pointer,
+    service::{AnyService, FileRoutes, Github, Service, Services},
+    statics::{
+        FAVICON, IMMUTABLE_AGE_SECS, INSTANCES_PATH, OPT, REDIRECT_AGE_SECS, STALE_RETRY_AGE_SECS,
+        TAG_AGE_SECS,
+    },
 };
 
 use actix_web::{
     dev::Service as _,
     get,
-    http::header::{self, CacheControl, CacheDirective, HeaderName, HeaderValue, LOCATION},
-    middleware, web, App, HttpMessage, HttpResponse, HttpServer, Responder,
+    http::header::{
+        self, CacheControl, CacheDirective, ContentDisposition, DispositionParam, DispositionType,
+        HeaderName, HeaderValue, LOCATION,
+    },
+    middleware,
+    web::{self, Bytes},
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, Scope,
 };
 use awc::{http::StatusCode, Client};
-use time_cache::{Cache, CacheResult};
-use tokio::sync::RwLock;
-use tracing::{debug, error, info, instrument};
+use futures_util::{future, stream, StreamExt};
+use tracing::{debug, error, info, instrument, Instrument};
 use tracing_actix_web::{RequestId, TracingLogger};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-#[instrument(skip(data, client), fields(path = data.path(), service = T::path()))]
+use std::sync::Arc;
+
+#[instrument(skip(service, services, cache, data, client), fields(path = data.path(), service = service.path()))]
 async fn proxy_file<T: Service>(
+    service: web::Data<T>,
+    services: web::Data<Services>,
+    cache: web::Data<State>,
     client: web::Data<Client>,
     data: web::Path<FilePath>,
-) -> Result<impl Responder> {
-    let response = client
-        .get(&T::raw_url(
-            &data.user,
-            &data.repo,
-            &data.commit,
-            &data.file,
-        ))
-        .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()))
-        .send()
-        .await?;
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse> {
+    if query.minify.is_some() {
+        return serve_minified(service.get_ref(), &cache, &client, &data, &data.file).await;
+    }
+    let req = client
+        .get(&service.raw_url(&data.user, &data.repo, &data.commit, &data.file))
+        .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()));
+    let mut response = service.authorize(req).send().await?;
     match response.status() {
         StatusCode::OK => {
             // mime type is guessed from the file extension
             let mime = mime_guess::from_path(&*data.file).first_or_octet_stream();
             info!(mime = %mime, "proxying file");
-            Ok(HttpResponse::Ok()
+            let mut builder = HttpResponse::Ok();
+            builder
                 .content_type(mime.as_ref())
                 .insert_header(CacheControl(vec![
                     CacheDirective::Public,
-                    CacheDirective::MaxAge(2_592_000_000),
-                ]))
-                .streaming(response))
+                    CacheDirective::MaxAge(IMMUTABLE_AGE_SECS),
+                ]));
+            if service.raw_base64() {
+                let body = response.body().limit(service::MAX_BODY_SIZE).await?;
+                return Ok(builder.body(service::decode_base64(&body)?));
+            }
+            if let Some(lfs_url) = service.lfs_url(&data.user, &data.repo) {
+                if lfs::might_be_pointer(response.headers()) {
+                    let (head, complete) = lfs::read_pointer_candidate(&mut response).await?;
+                    if !complete {
+                        // too large for a pointer, the rest of the file is streamed
+                        let head = stream::once(future::ready(Ok(head)));
+                        return Ok(builder.streaming(head.chain(response)));
+                    }
+                    if let Some(pointer) = Pointer::parse(&head) {
+                        info!(oid = %pointer.oid, "resolving LFS object");
+                        let object = service
+                            .fetch_lfs_object(&client, &lfs_url, &pointer)
+                            .await?;
+                        return Ok(builder.streaming(object));
+                    }
+                    return Ok(builder.body(head));
+                }
+            }
+            Ok(builder.streaming(response))
         }
         code => {
+            if code == StatusCode::NOT_FOUND {
+                // minified files that don't exist upstream are built from their source
+                if let Some(source) = minify::source_path(&data.file) {
+                    return serve_minified(service.get_ref(), &cache, &client, &data, &source)
+                        .await;
+                }
+                if let Some(response) =
+                    submodule::resolve(service.get_ref(), &services, &cache, &client, &data).await
+                {
+                    return Ok(response);
+                }
+            }
             error!(code = %code, "error from remote");
             Ok(HttpResponse::build(code).finish())
         }
     }
 }
 
-#[instrument(skip(cache, data, client), fields(path = data.path(), service = T::path()))]
-async fn redirect<T: Service>(
+/// Serves the minified version of `source`. Only files at full commit hashes are served, so the
+/// minified files are cached.
+async fn serve_minified<T: Service>(
+    service: &T,
+    cache: &State,
+    client: &Client,
+    data: &FilePath,
+    source: &str,
+) -> Result<HttpResponse> {
+    let key = data.to_file_key(service);
+    let content = if let Some(content) = cache.minified(&key) {
+        debug!("Loading minified file from cache");
+        content
+    } else {
+        info!(source = %source, "minifying file");
+        let url = service.raw_url(&data.user, &data.repo, &data.commit, source);
+        let content = Bytes::from(minify::minify(
+            source,
+            &service.fetch_raw(client, &url).await?,
+        )?);
+        cache.store_minified(key, content.clone());
+        content
+    };
+    let mime = mime_guess::from_path(&*data.file).first_or_octet_stream();
+    Ok(HttpResponse::Ok()
+        .content_type(mime.as_ref())
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(IMMUTABLE_AGE_SECS),
+        ]))
+        .body(content))
+}
+
+#[instrument(skip(service, cache, data, client, req), fields(path = data.path(), service = service.path()))]
+async fn redirect<T: Service + 'static>(
+    service: web::Data<T>,
     cache: web::Data<State>,
     client: web::Data<Client>,
     data: web::Path<FilePath>,
+    req: HttpRequest,
 ) -> Result<impl Responder> {
-    let invalid = {
-        let cache = cache.read().await;
-        let key = data.to_key::<T>();
-        match cache.get(&key) {
-            CacheResult::Cached(head) => {
-                debug!("Loading HEAD from cache");
-                return Ok(HttpResponse::SeeOther()
-                    .insert_header((
-                        LOCATION,
-                        T::redirect_url(&data.user, &data.repo, head, &data.file).as_str(),
-                    ))
-                    .insert_header(CacheControl(vec![
-                        CacheDirective::Public,
-                        CacheDirective::MaxAge(*REDIRECT_AGE_SECS),
-                    ]))
-                    .finish());
+    let mut data = data.into_inner();
+    let (head, max_age) =
+        if let Some((head, refresh)) = cache.stale(&data.to_key(service.get_ref())) {
+            // the stale head keeps being served until the refresh succeeds
+            if refresh {
+                info!("Serving stale HEAD, refreshing in the background");
+                let (service, cache, client, data) =
+                    (service.clone(), cache.clone(), client.clone(), data.clone());
+                actix_web::rt::spawn(
+                    async move {
+                        if let Err(e) = service.head(&client, &data, &cache).await {
+                            error!(error = %e, "failed to refresh stale HEAD");
+                        }
+                    }
+                    .in_current_span(),
+                );
+            } else {
+                info!("Serving stale HEAD");
             }
-            CacheResult::Invalid => true,
-            CacheResult::Empty => false,
-        }
-    };
-    if invalid {
-        let mut cache = cache.write().await;
-        debug!("Clearing cache. Removing invalid elements");
-        cache.clear();
-    }
-    info!("Redirecting");
-    T::request_head(data, cache, &client).await
+            (head, *STALE_RETRY_AGE_SECS)
+        } else {
+            let head = match service.head(&client, &data, &cache).await {
+                // `main/docs/-/file` might also be the file `docs/-/file` on `main`
+                Err(Error::Upstream(StatusCode::NOT_FOUND)) => {
+                    let Some(unseparated) = data.unseparated() else {
+                        return Err(Error::Upstream(StatusCode::NOT_FOUND));
+                    };
+                    let head = service.head(&client, &unseparated, &cache).await?;
+                    data = unseparated;
+                    head
+                }
+                head => head?,
+            };
+            let max_age = head.max_age();
+            (head, max_age)
+        };
+    info!(commit = %head.commit, "Redirecting");
+    Ok(service.redirect_to(&data, &head, max_age, req.query_string()))
 }
 
-#[instrument(skip(data, client), fields(path = data.path(), service = "gist"))]
-async fn serve_gist(client: web::Data<Client>, data: web::Path<FilePath>) -> Result<HttpResponse> {
-    let url = format!(
-        "https://gist.github.com/{}/{}/raw/{}/{}",
-        data.user, data.repo, data.commit, data.file
-    );
-    let response = client
-        .get(url)
-        .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()))
-        .send()
-        .await?;
+#[instrument(skip(service, data, client), fields(path = data.path(), service = service.path()))]
+async fn proxy_release(
+    service: web::Data<Github>,
+    client: web::Data<Client>,
+    data: web::Path<ReleasePath>,
+) -> Result<HttpResponse> {
+    if data.is_latest() {
+        let tag = service.latest_release(&client, &data).await?;
+        info!(tag = %tag, "Redirecting to latest release");
+        return Ok(HttpResponse::SeeOther()
+            .insert_header((
+                LOCATION,
+                service
+                    .release_url(&data.user, &data.repo, &tag, &data.asset)
+                    .as_str(),
+            ))
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(*REDIRECT_AGE_SECS),
+            ]))
+            .finish());
+    }
+    let asset = service.release_asset(&client, &data).await?;
+    let response = service.download(&client, &asset).send().await?;
     match response.status() {
         StatusCode::OK => {
-            // mime type is guessed from the file extension
-            let mime = mime_guess::from_path(&*data.file).first_or_octet_stream();
+            // prefer the mime type guessed from the file extension, uploads often use a generic one
+            let mime = mime_guess::from_path(&*data.asset)
+                .first_raw()
+                .unwrap_or(&asset.content_type);
+            info!(mime = %mime, "proxying release asset");
             Ok(HttpResponse::Ok()
-                .content_type(mime.as_ref())
+                .content_type(mime)
                 .insert_header(CacheControl(vec![
                     CacheDirective::Public,
-                    CacheDirective::MaxAge(2_592_000_000),
+                    CacheDirective::MaxAge(*TAG_AGE_SECS),
                 ]))
                 .streaming(response))
         }
-        code => Ok(HttpResponse::build(code).finish()),
+        code => {
+            error!(code = %code, "error from remote");
+            Ok(HttpResponse::build(code).finish())
+        }
     }
 }
 
+#[instrument(skip(service, cache, data, client), fields(path = data.path(), service = service.path()))]
+async fn list_files<T: Service>(
+    service: web::Data<T>,
+    cache: web::Data<State>,
+    client: web::Data<Client>,
+    data: web::Path<FilePath>,
+) -> Result<HttpResponse> {
+    let head = service.head(&client, &data, &cache).await?;
+    let entries = service
+        .list_files(&client, &data.with_commit(head.commit.clone()))
+        .await?;
+    info!(entries = entries.len(), "listing directory");
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(head.max_age()),
+        ]))
+        .json(entries))
+}
+
+#[instrument(skip(service, cache, data, client), fields(path = data.path(), service = service.path()))]
+async fn archive<T: Service>(
+    service: web::Data<T>,
+    cache: web::Data<State>,
+    client: web::Data<Client>,
+    data: web::Path<FilePath>,
+    query: web::Query<ArchiveQuery>,
+) -> Result<HttpResponse> {
+    let head = service.head(&client, &data, &cache).await?;
+    let data = FilePath {
+        commit: Arc::new(head.commit.clone()),
+        file: Arc::new(
+            query
+                .path
+                .as_deref()
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string(),
+        ),
+        ..data.into_inner()
+    };
+    let mut builder = HttpResponse::Ok();
+    builder
+        .content_type("application/gzip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}-{}.tar.gz",
+                data.repo, data.commit
+            ))],
+        })
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(head.max_age()),
+        ]));
+    if let Some(url) = service.archive_url(&data) {
+        info!(url = %url, "proxying archive");
+        let response = service.get(&client, &url).send().await?;
+        return match response.status() {
+            StatusCode::OK => Ok(builder.streaming(response)),
+            code => {
+                error!(code = %code, "error from remote");
+                Ok(HttpResponse::build(code).finish())
+            }
+        };
+    }
+    info!("building archive");
+    Ok(builder.body(service.build_archive(&client, &data).await?))
+}
+
+#[instrument(skip(services, cache, client))]
+async fn combine(
+    services: web::Data<Services>,
+    cache: web::Data<State>,
+    client: web::Data<Client>,
+    parts: web::Path<String>,
+) -> Result<HttpResponse> {
+    let parts = combine::fetch_parts(&services, &client, &cache, &parts).await?;
+    // mime type is guessed from the file extension of the first file
+    let mime = mime_guess::from_path(&*parts[0].data.file).first_or_octet_stream();
+    // a single moving ref makes the whole response move
+    let max_age = if parts.iter().all(|part| part.data.is_full_commit()) {
+        IMMUTABLE_AGE_SECS
+    } else {
+        *REDIRECT_AGE_SECS
+    };
+    info!(mime = %mime, parts = parts.len(), "combining files");
+    let mut body = Vec::new();
+    for part in &parts {
+        body.extend_from_slice(&part.content);
+        if !part.content.ends_with(b"\n") {
+            body.push(b'\n');
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(mime.as_ref())
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(max_age),
+        ]))
+        .body(body))
+}
+
 #[get("/favicon.ico")]
 #[allow(clippy::unused_async)]
 #[instrument]
@@ -132,31 +352,105 @@ async fn favicon32() -> HttpResponse {
         .content_type("image/png")
         .insert_header(CacheControl(vec![
             CacheDirective::Public,
-            CacheDirective::MaxAge(2_592_000_000),
+            CacheDirective::MaxAge(IMMUTABLE_AGE_SECS),
         ]))
         .body(FAVICON)
 }
 
 #[allow(clippy::unused_async)]
-#[instrument(skip(cache, data), fields(path = data.path(), service = T::path()))]
+#[instrument(skip(service, cache, data), fields(path = data.path(), service = service.path()))]
 async fn purge_local_cache<T: Service>(
+    service: web::Data<T>,
     cache: web::Data<State>,
     data: web::Path<FilePath>,
 ) -> HttpResponse {
-    let mut cache = cache.write().await;
     info!("Invalidating local cache");
-    let key = data.to_key::<T>();
+    let key = data.to_key(service.get_ref());
     cache.invalidate(&key);
     HttpResponse::Ok().finish()
 }
 
-#[instrument(skip(data, client), fields(path = data.path(), service = T::path()))]
+#[instrument(skip(service, data, client), fields(path = data.path(), service = service.path()))]
 async fn purge_cf_cache<T: Service>(
+    service: web::Data<T>,
     client: web::Data<Client>,
     data: web::Path<FilePath>,
 ) -> Result<HttpResponse> {
     info!("purging cache");
-    Cloudflare::purge_cache::<T>(&client, &data.path()).await
+    let path = service.redirect_url(&data.user, &data.repo, &data.commit, &data.file);
+    Cloudflare::purge_cache(&client, &path).await
+}
+
+/// Registers the routes of a service instance: files below `/{path}` and directory listings
+/// below `/api/list/{path}`.
+fn register<T: Service + Clone + 'static>(cfg: &mut web::ServiceConfig, service: T) {
+    let files = web::scope(&format!("/{}", service.path()));
+    register_scope(cfg, files, service);
+}
+
+/// Like `register` but also serves release assets, which take precedence over files in a
+/// `releases` branch.
+fn register_github(cfg: &mut web::ServiceConfig, github: Github) {
+    let files = web::scope(&format!("/{}", github.path())).route(
+        "/{user}/{repo}/releases/{tag}/{asset}",
+        web::get().to(proxy_release),
+    );
+    register_scope(cfg, files, github);
+}
+
+fn register_scope<T: Service + Clone + 'static>(
+    cfg: &mut web::ServiceConfig,
+    files: Scope,
+    service: T,
+) {
+    cfg.service(list_scope(service.clone()))
+        .service(archive_scope(service.clone()))
+        .service(service_routes(files, service));
+}
+
+/// Archive routes of `service`. Refs may contain slashes, since the archive name terminates them.
+fn archive_scope<T: Service + 'static>(service: T) -> Scope {
+    let user = service.user_pattern();
+    let tarball = format!("/{user}/{{repo}}/{{commit:.+}}.tar.gz");
+    web::scope(&format!("/archive/{}", service.path()))
+        .app_data(web::Data::new(service))
+        .route(&tarball, web::get().to(archive::<T>))
+}
+
+/// Directory listing routes of `service`. The directory may be omitted to list the root.
+fn list_scope<T: Service + 'static>(service: T) -> Scope {
+    let user = service.user_pattern();
+    let FileRoutes {
+        pinned: [pinned_separated, pinned],
+        refs: [separated, dir],
+    } = service.file_routes();
+    let root = format!("/{user}/{{repo}}/{{commit}}");
+    let separated_root = format!("/{user}/{{repo}}/{{commit:[^/]+/.+}}/-");
+    web::scope(&format!("/api/list/{}", service.path()))
+        .app_data(web::Data::new(service))
+        .route(&pinned_separated, web::get().to(list_files::<T>))
+        .route(&pinned, web::get().to(list_files::<T>))
+        .route(&separated, web::get().to(list_files::<T>))
+        .route(&separated_root, web::get().to(list_files::<T>))
+        .route(&dir, web::get().to(list_files::<T>))
+        .route(&root, web::get().to(list_files::<T>))
+}
+
+/// Adds the generic file routes of `service` to `scope`.
+fn service_routes<T: Service + 'static>(scope: Scope, service: T) -> Scope {
+    let routes = service.file_routes();
+    let mut scope = scope.app_data(web::Data::new(service));
+    for pinned in &routes.pinned {
+        scope = scope
+            .route(pinned, web::get().to(proxy_file::<T>))
+            .route(pinned, web::delete().to(purge_cf_cache::<T>));
+    }
+    for any in &routes.refs {
+        scope = scope
+            .route(any, web::get().to(redirect::<T>))
+            .route(any, web::delete().to(purge_local_cache::<T>));
+    }
+    scope
 }
 
 fn init_logging() {
@@ -173,7 +467,14 @@ fn init_logging() {
 async fn main() -> Result<()> {
     init_logging();
 
-    let state = web::Data::new(RwLock::new(Cache::<data::Key, String>::new(REDIRECT_AGE)));
+    let instances = if let Some(path) = &*INSTANCES_PATH {
+        info!(path = %path, "loading service instances");
+        Instances::load(&**path)?
+    } else {
+        Instances::default()
+    };
+    let state = web::Data::new(State::new());
+    let services = web::Data::new(Services::new(&instances));
     Ok(HttpServer::new(move || {
         App::new()
             // set the request id in the `x-request-id` response header
@@ -197,58 +498,25 @@ async fn main() -> Result<()> {
             .wrap(TracingLogger::default())
             .wrap(middleware::NormalizePath::trim())
             .service(favicon32)
-            .route(
-                "/github/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::get().to(proxy_file::<Github>),
-            )
-            .route(
-                "/github/{user}/{repo}/{commit}/{file:.*}",
-                web::get().to(redirect::<Github>),
-            )
-            .route(
-                "/github/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::delete().to(purge_cf_cache::<Github>),
-            )
-            .route(
-                "/github/{user}/{repo}/{commit}/{file:.*}",
-                web::delete().to(purge_local_cache::<Github>),
-            )
-            .route(
-                "/bitbucket/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::get().to(proxy_file::<Bitbucket>),
-            )
-            .route(
-                "/bitbucket/{user}/{repo}/{commit}/{file:.*}",
-                web::get().to(redirect::<Bitbucket>),
-            )
-            .route(
-                "/bitbucket/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::delete().to(purge_cf_cache::<Bitbucket>),
-            )
-            .route(
-                "/bitbucket/{user}/{repo}/{commit}/{file:.*}",
-                web::delete().to(purge_local_cache::<Bitbucket>),
-            )
-            .route(
-                "/gitlab/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::get().to(proxy_file::<GitLab>),
-            )
-            .route(
-                "/gitlab/{user}/{repo}/{commit}/{file:.*}",
-                web::get().to(redirect::<GitLab>),
-            )
-            .route(
-                "/gitlab/{user}/{repo}/{commit:[0-9a-fA-F]{40}}/{file:.*}",
-                web::delete().to(purge_cf_cache::<GitLab>),
-            )
-            .route(
-                "/gitlab/{user}/{repo}/{commit}/{file:.*}",
-                web::delete().to(purge_cf_cache::<GitLab>),
-            )
-            .route(
-                "/gist/{user}/{repo}/{commit}/{file:.*}",
-                web::get().to(serve_gist),
-            )
+            .app_data(services.clone())
+            .configure(|cfg| {
+                for service in services.iter() {
+                    match service.clone() {
+                        AnyService::Github(service) => register_github(cfg, service),
+                        AnyService::Gist(service) => register(cfg, service),
+                        AnyService::Bitbucket(service) => register(cfg, service),
+                        AnyService::GitLab(service) => register(cfg, service),
+                        AnyService::Gitea(service) => register(cfg, service),
+                        AnyService::SourceHut(service) => register(cfg, service),
+                        AnyService::Azure(service) => register(cfg, service),
+                        AnyService::HuggingFace(service) => register(cfg, service),
+                        AnyService::BitbucketServer(service) => register(cfg, service),
+                        AnyService::Gitiles(service) => register(cfg, service),
+                        AnyService::Cgit(service) => register(cfg, service),
+                    }
+                }
+            })
+            .route("/combine/{parts:.*}", web::get().to(combine))
             .service(actix_files::Files::new("/", "./public").index_file("index.html"))
     })
     .workers(OPT.workers)
@@ -0,0 +1,31 @@
+//! On-the-fly minification of JavaScript and CSS files.
+
+use crate::error::{Error, Result};
+
+use actix_web::http::StatusCode;
+
+/// Upper bound for the size of files that are minified. Minified files are cached for a long
+/// time, so this bounds the memory used by the cache.
+const MAX_SOURCE_SIZE: usize = 1024 * 1024;
+
+/// Source file of a minified file, e.g. `dist/lib.js` for `dist/lib.min.js`.
+pub(crate) fn source_path(file: &str) -> Option<String> {
+    [".js", ".css"].into_iter().find_map(|extension| {
+        let stem = file.strip_suffix(extension)?.strip_suffix(".min")?;
+        Some(format!("{stem}{extension}"))
+    })
+}
+
+/// Minifies `content` based on the file extension of `file`.
+pub(crate) fn minify(file: &str, content: &[u8]) -> Result<String> {
+    if content.len() > MAX_SOURCE_SIZE {
+        return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    let content = std::str::from_utf8(content).map_err(|_| Error::Minify("invalid UTF-8"))?;
+    let minified = match file.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("js" | "mjs") => minifier::js::minify(content).map(|js| js.to_string()),
+        Some("css") => minifier::css::minify(content).map(|css| css.to_string()),
+        _ => Err("unsupported file type"),
+    };
+    minified.map_err(Error::Minify)
+}
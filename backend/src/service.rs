@@ -1,56 +1,150 @@
 use crate::{
-    data::{self, FilePath, State},
-    error::Result,
-    statics::{self, load_env_var, GITHUB_AUTH_QUERY, OPT, REDIRECT_AGE_SECS},
+    config::{InstanceConfig, InstanceKind, Instances},
+    data::{self, Entry, EntryKind, FilePath, Head, RefKind, ReleasePath, State, Tag},
+    error::{Error, Result},
+    git,
+    lfs::{self, BatchRequest, BatchResponse, Pointer},
+    statics::{self, load_env_var, AZURE_TOKEN, GITHUB_AUTH_QUERY, HF_TOKEN, OPT},
 };
 
 use actix_web::{
+    dev::{Decompress, Payload},
     http::{
         header::{self, CacheControl, CacheDirective, LOCATION},
         StatusCode,
     },
-    web, HttpResponse,
+    web::Bytes,
+    HttpResponse,
 };
-use awc::Client;
-use serde::Deserialize;
-use tracing::error;
+use awc::{Client, ClientRequest, ClientResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use flate2::{write::GzEncoder, Compression};
+use futures_util::StreamExt;
+use percent_encoding::{utf8_percent_encode, AsciiSet, PercentEncode, NON_ALPHANUMERIC};
+use semver::{Version, VersionReq};
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::{debug, error};
 
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
 
-pub(crate) trait ApiResponse {
-    fn commit_ref(&self) -> &str;
+/// Characters that must be escaped in a single path segment or query value.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encodes `value` so it can be used as a single path segment or query value, e.g. for
+/// refs containing slashes.
+fn encode(value: &str) -> PercentEncode<'_> {
+    utf8_percent_encode(value, COMPONENT)
 }
 
-#[derive(Deserialize)]
-pub(crate) struct GitHubApiResponse(String);
+/// Percent-encodes each segment of `path`, keeping the slashes between them, e.g. for fully
+/// qualified refs like `refs/heads/release/1.2`.
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| encode(segment).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
-impl ApiResponse for GitHubApiResponse {
-    fn commit_ref(&self) -> &str {
-        &self.0
+/// Upper bound for the number of files in archives that are built from individual files.
+const MAX_ARCHIVE_FILES: usize = 1000;
+
+/// Upper bound for the total size in bytes of files in archives that are built from individual
+/// files, which are held in memory until the archive is complete.
+const MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Upper bound for the number of directories listed to build an archive. Each listing is a
+/// request against the service's API, which might be rate limited.
+const MAX_ARCHIVE_DIRS: usize = 100;
+
+/// Upper bound for the size of upstream responses that are buffered instead of streamed, e.g.
+/// parts of combined files, files added to archives, files that are minified or base64 encoded
+/// files. Larger responses fail with `413 Payload Too Large`.
+pub(crate) const MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// Decodes a base64 encoded file. Line breaks are ignored.
+pub(crate) fn decode_base64(body: &[u8]) -> Result<Bytes> {
+    let body: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    Ok(BASE64.decode(body)?.into())
+}
+
+/// Route patterns of files, each group ordered by priority.
+pub(crate) struct FileRoutes {
+    /// Files at full commit hashes. These are matched first, so full commit hashes are never
+    /// mistaken for refs containing slashes
+    pub(crate) pinned: [String; 2],
+    /// Files at any other ref
+    pub(crate) refs: [String; 2],
+}
+
+impl FileRoutes {
+    fn new(user: &str) -> Self {
+        // refs containing slashes are terminated by `/-/`, e.g. `release/1.2/-/README.md`. Refs
+        // without slashes don't need it, so `main/-/README.md` is still the file `-/README.md`
+        Self {
+            pinned: [
+                format!("/{user}/{{repo}}/{{commit:[0-9a-fA-F]{{40}}}}/-/{{file:.*}}"),
+                format!("/{user}/{{repo}}/{{commit:[0-9a-fA-F]{{40}}}}/{{file:.*}}"),
+            ],
+            refs: [
+                format!("/{user}/{{repo}}/{{commit:[^/]+/.+?}}/-/{{file:.*}}"),
+                format!("/{user}/{{repo}}/{{commit}}/{{file:.*}}"),
+            ],
+        }
+    }
+
+    /// All patterns in the order they are matched.
+    pub(crate) fn all(&self) -> impl Iterator<Item = &str> {
+        self.pinned.iter().chain(&self.refs).map(String::as_str)
+    }
+}
+
+/// Last segment of `path`.
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Sends `req` and returns the response body. Responses other than `200 OK` are turned into
+/// `Error::Upstream`.
+async fn send(req: ClientRequest) -> Result<Bytes> {
+    let mut response = req.send().await?;
+    match response.status() {
+        StatusCode::OK => Ok(response.body().limit(MAX_BODY_SIZE).await?),
+        code => {
+            error!(code = %code, "request failed");
+            Err(Error::Upstream(code))
+        }
     }
 }
 
+pub(crate) trait ApiResponse {
+    fn commit_ref(&self) -> &str;
+}
+
 #[derive(Deserialize)]
 pub(crate) struct BitbucketApiResponse {
-    values: Vec<BitbucketEntry>,
+    target: BitbucketTarget,
 }
 
 #[derive(Deserialize)]
-struct BitbucketEntry {
+struct BitbucketTarget {
     hash: String,
 }
 
 impl ApiResponse for BitbucketApiResponse {
     fn commit_ref(&self) -> &str {
-        &self.values[0].hash
+        &self.target.hash
     }
 }
 
-#[derive(Deserialize)]
-struct GitLabProject {
-    id: u64,
-}
-
+/// Response for both, branches and tags. Tags are peeled to the tagged commit.
 #[derive(Deserialize)]
 pub(crate) struct GitLabApiResponse {
     commit: GitLabCommit,
@@ -67,274 +161,2511 @@ impl ApiResponse for GitLabApiResponse {
     }
 }
 
-#[async_trait::async_trait(?Send)]
-pub(crate) trait Service: Sized {
-    type Response: for<'de> serde::Deserialize<'de> + ApiResponse;
+#[derive(Deserialize)]
+pub(crate) struct BitbucketCommitResponse {
+    hash: String,
+}
 
-    fn raw_url(user: &str, repo: &str, commit: &str, file: &str) -> String;
+impl ApiResponse for BitbucketCommitResponse {
+    fn commit_ref(&self) -> &str {
+        &self.hash
+    }
+}
 
-    fn cache_service() -> data::Service;
+#[derive(Deserialize)]
+pub(crate) struct GitLabCommitResponse {
+    id: String,
+}
 
-    fn api_url(path: &FilePath) -> String;
+impl ApiResponse for GitLabCommitResponse {
+    fn commit_ref(&self) -> &str {
+        &self.id
+    }
+}
 
-    fn path() -> &'static str;
+#[derive(Deserialize)]
+pub(crate) struct GiteaApiResponse {
+    commit: GiteaCommit,
+}
 
-    fn api_accept() -> Option<&'static str> {
-        None
+#[derive(Deserialize)]
+struct GiteaCommit {
+    id: String,
+}
+
+impl ApiResponse for GiteaApiResponse {
+    fn commit_ref(&self) -> &str {
+        &self.commit.id
     }
+}
 
-    fn redirect_url(user: &str, repo: &str, commit: &str, file: &str) -> String;
+#[derive(Deserialize)]
+pub(crate) struct GiteaTagResponse {
+    commit: GiteaTagCommit,
+}
 
-    async fn request_head(
-        data: web::Path<FilePath>,
-        cache: web::Data<State>,
-        client: &Client,
-    ) -> Result<HttpResponse> {
-        let req = client
-            .get(&Self::api_url(&data))
-            .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()));
-        let req = if let Some(accept) = Self::api_accept() {
-            req.insert_header((header::ACCEPT, accept))
-        } else {
-            req
-        };
-        let mut response = req.send().await?;
-        let status = response.status();
-        Ok(match status {
-            StatusCode::OK => {
-                let resp = response.json::<Self::Response>().await?;
-                let mut cache = cache.write().await;
-                let key = data.to_key::<Self>();
-                cache.store(key, resp.commit_ref().to_string());
-                HttpResponse::SeeOther()
-                    .insert_header((
-                        LOCATION,
-                        Self::redirect_url(&data.user, &data.repo, resp.commit_ref(), &data.file)
-                            .as_str(),
-                    ))
-                    .insert_header(CacheControl(vec![
-                        CacheDirective::Public,
-                        CacheDirective::MaxAge(*REDIRECT_AGE_SECS),
-                    ]))
-                    .finish()
-            }
-            code => {
-                error!(code = %code, "request failed");
-                HttpResponse::build(code).finish()
-            }
-        })
+#[derive(Deserialize)]
+struct GiteaTagCommit {
+    sha: String,
+}
+
+impl ApiResponse for GiteaTagResponse {
+    fn commit_ref(&self) -> &str {
+        &self.commit.sha
     }
 }
 
-pub(crate) struct Github;
+#[derive(Deserialize)]
+pub(crate) struct GiteaCommitResponse {
+    sha: String,
+}
 
-impl Github {
-    pub(crate) fn auth_query() -> Option<Cow<'static, str>> {
-        match (
-            OPT.github_id
-                .as_ref()
-                .map(Cow::from)
-                .or_else(|| load_env_var("GITHUB_CLIENT_ID")),
-            OPT.github_secret
-                .as_ref()
-                .map(Cow::from)
-                .or_else(|| load_env_var("GITHUB_CLIENT_SECRET")),
-        ) {
-            (Some(id), Some(secret)) => {
-                Some(format!("?client_id={id}&client_secret={secret}").into())
-            }
-            _ => None,
-        }
+impl ApiResponse for GiteaCommitResponse {
+    fn commit_ref(&self) -> &str {
+        &self.sha
     }
 }
 
-#[async_trait::async_trait(?Send)]
-impl Service for Github {
-    type Response = GitHubApiResponse;
+#[derive(Deserialize)]
+pub(crate) struct AzureApiResponse {
+    value: Vec<AzureRef>,
+}
 
-    fn cache_service() -> data::Service {
-        data::Service::GitHub
-    }
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureRef {
+    name: String,
+    object_id: String,
+    /// Commit of an annotated tag, only set if requested with `peelTags`
+    peeled_object_id: Option<String>,
+}
 
-    fn path() -> &'static str {
-        "github"
+/// Entry of the tag listings of GitHub and Gitea.
+#[derive(Deserialize)]
+pub(crate) struct TagListResponse {
+    name: String,
+    commit: GiteaTagCommit,
+}
+
+impl From<TagListResponse> for Tag {
+    fn from(tag: TagListResponse) -> Self {
+        Tag::new(tag.name, tag.commit.sha)
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GitLabTagListResponse {
+    name: String,
+    commit: GitLabCommit,
+}
 
-    fn api_accept() -> Option<&'static str> {
-        Some("application/vnd.github.3.sha")
+impl From<GitLabTagListResponse> for Tag {
+    fn from(tag: GitLabTagListResponse) -> Self {
+        Tag::new(tag.name, tag.commit.id)
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BitbucketTagListResponse {
+    values: Vec<BitbucketTag>,
+    /// URL of the next page, if any
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketTag {
+    name: String,
+    target: BitbucketTarget,
+}
+
+/// Repository metadata of GitHub, GitLab and Gitea.
+#[derive(Deserialize)]
+pub(crate) struct RepositoryResponse {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BitbucketRepositoryResponse {
+    mainbranch: BitbucketBranch,
+}
+
+#[derive(Deserialize)]
+struct BitbucketBranch {
+    name: String,
+}
 
-    fn raw_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("https://raw.githubusercontent.com/{user}/{repo}/{commit}/{file}")
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AzureRepositoryResponse {
+    /// Fully qualified ref, e.g. `refs/heads/main`. Missing for empty repositories.
+    default_branch: Option<String>,
+}
+
+/// Entry of the contents APIs of GitHub and Gitea.
+#[derive(Deserialize)]
+pub(crate) struct ContentsResponse {
+    name: String,
+    size: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+    download_url: Option<String>,
+}
+
+impl From<ContentsResponse> for Entry {
+    fn from(entry: ContentsResponse) -> Self {
+        let kind = match (entry.kind.as_str(), entry.download_url) {
+            ("dir", _) => EntryKind::Directory,
+            ("symlink", _) => EntryKind::Symlink,
+            // GitHub lists submodules as files without a download URL
+            ("submodule", _) | ("file", None) => EntryKind::Submodule,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: entry.name,
+            size: matches!(kind, EntryKind::File).then_some(entry.size),
+            kind,
+            sha: Some(entry.sha),
+        }
     }
+}
 
-    fn api_url(path: &FilePath) -> String {
-        format!(
-            "https://api.github.com/repos/{}/{}/commits/{}{}",
-            path.user, path.repo, path.commit, *GITHUB_AUTH_QUERY
-        )
+/// Response of the contents APIs of GitHub and Gitea, which return a single
+/// object instead of a list if the path is a file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ContentsListing {
+    Directory(Vec<ContentsResponse>),
+    File(serde::de::IgnoredAny),
+}
+
+impl ContentsListing {
+    /// Returns the entries of a directory or `400 Bad Request` for a file.
+    fn entries(self) -> Result<Vec<Entry>> {
+        match self {
+            ContentsListing::Directory(entries) => {
+                Ok(entries.into_iter().map(Entry::from).collect())
+            }
+            ContentsListing::File(_) => Err(Error::Upstream(StatusCode::BAD_REQUEST)),
+        }
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GitLabTreeResponse {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    mode: String,
+}
 
-    fn redirect_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("/github/{user}/{repo}/{commit}/{file}")
+impl From<GitLabTreeResponse> for Entry {
+    fn from(entry: GitLabTreeResponse) -> Self {
+        let kind = match (entry.kind.as_str(), entry.mode.as_str()) {
+            ("tree", _) => EntryKind::Directory,
+            ("commit", _) => EntryKind::Submodule,
+            (_, "120000") => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: entry.name,
+            size: None,
+            kind,
+            sha: Some(entry.id),
+        }
     }
+}
 
-    async fn request_head(
-        data: web::Path<FilePath>,
-        cache: web::Data<State>,
-        client: &Client,
-    ) -> Result<HttpResponse> {
-        let req = client
-            .get(&Self::api_url(&data))
-            .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()));
-        let req = if let Some(accept) = Self::api_accept() {
-            req.insert_header((header::ACCEPT, accept))
+#[derive(Deserialize)]
+pub(crate) struct BitbucketSrcResponse {
+    values: Vec<BitbucketSrcEntry>,
+    /// URL of the next page, if any
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketSrcEntry {
+    /// Path relative to the repository root
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+    size: Option<u64>,
+    #[serde(default)]
+    attributes: Vec<String>,
+}
+
+impl From<BitbucketSrcEntry> for Entry {
+    fn from(entry: BitbucketSrcEntry) -> Self {
+        let kind = if entry.kind == "commit_directory" {
+            EntryKind::Directory
+        } else if entry.attributes.iter().any(|a| a == "link") {
+            EntryKind::Symlink
+        } else if entry.attributes.iter().any(|a| a == "subrepository") {
+            EntryKind::Submodule
         } else {
-            req
+            EntryKind::File
         };
-        let mut response = req.send().await?;
-        let status = response.status();
-        Ok(match status {
-            StatusCode::OK => {
-                let resp = response.body().await?;
-                let head = String::from_utf8_lossy(resp.as_ref());
-                let mut cache = cache.write().await;
-                let key = data.to_key::<Self>();
-                cache.store(key, head.to_string());
-                HttpResponse::SeeOther()
-                    .insert_header((
-                        LOCATION,
-                        Self::redirect_url(&data.user, &data.repo, &head, &data.file).as_str(),
-                    ))
-                    .insert_header(CacheControl(vec![
-                        CacheDirective::Public,
-                        CacheDirective::MaxAge(*REDIRECT_AGE_SECS),
-                    ]))
-                    .finish()
-            }
-            code => {
-                error!(code = %code, "request failed");
-                HttpResponse::build(code).finish()
-            }
-        })
+        Entry {
+            name: file_name(&entry.path).to_string(),
+            size: entry.size,
+            kind,
+            sha: None,
+        }
     }
 }
 
-pub(crate) struct Bitbucket;
+#[derive(Deserialize)]
+pub(crate) struct AzureItemsResponse {
+    value: Vec<AzureItem>,
+}
 
-#[async_trait::async_trait(?Send)]
-impl Service for Bitbucket {
-    type Response = BitbucketApiResponse;
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureItem {
+    object_id: String,
+    git_object_type: String,
+    /// Absolute path, starting with `/`
+    path: String,
+    size: Option<u64>,
+}
 
-    fn cache_service() -> data::Service {
-        data::Service::Bitbucket
+impl From<AzureItem> for Entry {
+    fn from(item: AzureItem) -> Self {
+        let kind = match item.git_object_type.as_str() {
+            "tree" => EntryKind::Directory,
+            "commit" => EntryKind::Submodule,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: file_name(&item.path).to_string(),
+            size: item.size,
+            kind,
+            sha: Some(item.object_id),
+        }
     }
+}
 
-    fn path() -> &'static str {
-        "bitbucket"
-    }
+#[derive(Deserialize)]
+pub(crate) struct GistRevisionResponse {
+    files: HashMap<String, GistFile>,
+}
 
-    fn raw_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("https://bitbucket.org/{user}/{repo}/raw/{commit}/{file}")
-    }
+#[derive(Deserialize)]
+struct GistFile {
+    size: u64,
+}
 
-    fn api_url(path: &FilePath) -> String {
-        format!(
-            "https://api.bitbucket.org/2.0/repositories/{}/{}/commits/{}?pagelen=1",
-            path.user, path.repo, path.commit
-        )
-    }
+/// Page of a paginated Bitbucket Server response.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BitbucketServerPage<T> {
+    values: Vec<T>,
+    is_last_page: bool,
+    next_page_start: Option<u64>,
+}
+
+/// Branch or tag. Tags are peeled to the tagged commit.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitbucketServerRef {
+    display_id: String,
+    latest_commit: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BitbucketServerCommitResponse {
+    id: String,
+}
 
-    fn redirect_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("/bitbucket/{user}/{repo}/{commit}/{file}")
+impl ApiResponse for BitbucketServerCommitResponse {
+    fn commit_ref(&self) -> &str {
+        &self.id
     }
 }
 
-pub(crate) struct GitLab;
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BitbucketServerDefaultBranchResponse {
+    display_id: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BitbucketServerBrowseResponse {
+    children: BitbucketServerPage<BitbucketServerChild>,
+}
 
-#[async_trait::async_trait(?Send)]
-impl Service for GitLab {
-    type Response = GitLabApiResponse;
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitbucketServerChild {
+    path: BitbucketServerPath,
+    #[serde(rename = "type")]
+    kind: String,
+    size: Option<u64>,
+    /// Hash of the blob or, for submodules, the pinned commit
+    content_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketServerPath {
+    name: String,
+}
 
-    fn cache_service() -> data::Service {
-        data::Service::GitLab
+impl From<BitbucketServerChild> for Entry {
+    fn from(child: BitbucketServerChild) -> Self {
+        let kind = match child.kind.as_str() {
+            "DIRECTORY" => EntryKind::Directory,
+            "SUBMODULE" => EntryKind::Submodule,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: child.path.name,
+            size: child.size,
+            kind,
+            sha: child.content_id,
+        }
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GitilesRef {
+    value: String,
+    /// Commit of an annotated tag
+    peeled: Option<String>,
+    /// Ref a symbolic ref, like `HEAD`, points to
+    target: Option<String>,
+}
 
-    fn path() -> &'static str {
-        "gitlab"
+impl GitilesRef {
+    fn commit(self) -> String {
+        self.peeled.unwrap_or(self.value)
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GitilesCommitResponse {
+    commit: String,
+}
 
-    fn raw_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("https://gitlab.com/{user}/{repo}/raw/{commit}/{file}")
+impl ApiResponse for GitilesCommitResponse {
+    fn commit_ref(&self) -> &str {
+        &self.commit
     }
+}
 
-    fn api_url(path: &FilePath) -> String {
-        let repo_pattern = format!("{}/{}", path.user, path.repo).replace('/', "%2F");
-        format!("https://gitlab.com/api/v4/projects/{repo_pattern}")
+#[derive(Deserialize)]
+pub(crate) struct GitilesTreeResponse {
+    entries: Vec<GitilesTreeEntry>,
+}
+
+#[derive(Deserialize)]
+struct GitilesTreeEntry {
+    mode: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
+    name: String,
+    size: Option<u64>,
+}
+
+impl From<GitilesTreeEntry> for Entry {
+    fn from(entry: GitilesTreeEntry) -> Self {
+        let kind = match (entry.kind.as_str(), entry.mode) {
+            ("tree", _) => EntryKind::Directory,
+            ("commit", _) => EntryKind::Submodule,
+            (_, 0o120_000) => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        Entry {
+            name: entry.name,
+            size: entry.size.filter(|_| matches!(kind, EntryKind::File)),
+            kind,
+            sha: Some(entry.id),
+        }
     }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HuggingFaceRefsResponse {
+    branches: Vec<HuggingFaceRef>,
+    tags: Vec<HuggingFaceRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HuggingFaceRef {
+    name: String,
+    target_commit: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HuggingFaceRevisionResponse {
+    sha: String,
+}
 
-    fn redirect_url(user: &str, repo: &str, commit: &str, file: &str) -> String {
-        format!("/gitlab/{user}/{repo}/{commit}/{file}")
+impl ApiResponse for HuggingFaceRevisionResponse {
+    fn commit_ref(&self) -> &str {
+        &self.sha
     }
+}
 
-    async fn request_head(
-        data: web::Path<FilePath>,
-        cache: web::Data<State>,
-        client: &Client,
-    ) -> Result<HttpResponse> {
-        let req = client
-            .get(&Self::api_url(&data))
-            .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()));
-        let req = if let Some(accept) = Self::api_accept() {
-            req.insert_header((header::ACCEPT, accept))
+#[derive(Deserialize)]
+pub(crate) struct HuggingFaceTreeResponse {
+    #[serde(rename = "type")]
+    kind: String,
+    oid: String,
+    size: u64,
+    /// Path relative to the repository root
+    path: String,
+}
+
+impl From<HuggingFaceTreeResponse> for Entry {
+    fn from(entry: HuggingFaceTreeResponse) -> Self {
+        let kind = if entry.kind == "directory" {
+            EntryKind::Directory
         } else {
-            req
+            EntryKind::File
         };
-        let mut response = req.send().await?;
-        Ok(match response.status() {
-            StatusCode::OK => {
-                let resp = response.json::<GitLabProject>().await?;
-                let repo_id = resp.id;
-                let mut respo = client
-                    .get(format!(
-                        "https://gitlab.com/api/v4/projects/{}/repository/branches/{}",
-                        repo_id, data.commit
-                    ))
-                    .send()
-                    .await?;
-                match respo.status() {
-                    StatusCode::OK => {
-                        let resp = respo.json::<Self::Response>().await?;
-                        let mut cache = cache.write().await;
-                        let key = data.to_key::<Self>();
-                        cache.store(key, resp.commit_ref().to_string());
-                        HttpResponse::SeeOther()
-                            .insert_header((
-                                LOCATION,
-                                Self::redirect_url(
-                                    &data.user,
-                                    &data.repo,
-                                    resp.commit_ref(),
-                                    &data.file,
-                                )
-                                .as_str(),
-                            ))
-                            .insert_header(CacheControl(vec![
-                                CacheDirective::Public,
-                                CacheDirective::MaxAge(*REDIRECT_AGE_SECS),
-                            ]))
-                            .finish()
-                    }
-                    code => {
-                        error!(code = %code, "request failed");
-                        HttpResponse::build(code).finish()
-                    }
-                }
-            }
-            code => {
-                error!(code = %code, "request failed");
-                HttpResponse::build(code).finish()
-            }
-        })
+        Entry {
+            name: file_name(&entry.path).to_string(),
+            size: matches!(kind, EntryKind::File).then_some(entry.size),
+            kind,
+            sha: Some(entry.oid),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReleaseResponse {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReleaseAsset {
+    name: String,
+    /// API URL of the asset, responds with the content if requested as `application/octet-stream`
+    pub(crate) url: String,
+    pub(crate) content_type: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GistResponse {
+    /// Revisions of the gist, the most recent first
+    history: Vec<GistRevision>,
+}
+
+#[derive(Deserialize)]
+struct GistRevision {
+    version: String,
+}
+
+/// Location and credentials of a service instance.
+#[derive(Clone)]
+pub(crate) struct Instance {
+    name: Cow<'static, str>,
+    /// Web interface, also used to derive git URLs
+    url: Cow<'static, str>,
+    api_url: Cow<'static, str>,
+    raw_url: Cow<'static, str>,
+    token: Option<String>,
+    cache_service: data::Service,
+}
+
+impl Instance {
+    fn new(
+        name: &'static str,
+        url: &'static str,
+        api_url: &'static str,
+        raw_url: &'static str,
+        cache_service: data::Service,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            url: url.into(),
+            api_url: api_url.into(),
+            raw_url: raw_url.into(),
+            token: None,
+            cache_service,
+        }
+    }
+
+    /// Creates an instance from its configuration. `api_path` and `raw_path` are appended to the
+    /// base URL if no explicit API or raw URL is configured.
+    fn from_config(config: &InstanceConfig, api_path: &str, raw_path: &str) -> Self {
+        let url = config.url.trim_end_matches('/');
+        Self {
+            name: config.name.clone().into(),
+            url: url.to_string().into(),
+            api_url: config
+                .api_url
+                .clone()
+                .unwrap_or_else(|| format!("{url}{api_path}"))
+                .into(),
+            raw_url: config
+                .raw_url
+                .clone()
+                .unwrap_or_else(|| format!("{url}{raw_path}"))
+                .into(),
+            token: config.token.clone(),
+            cache_service: data::Service::Instance(Arc::new(config.name.clone())),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+pub(crate) trait Service: Sized {
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String;
+
+    fn cache_service(&self) -> data::Service;
+
+    /// Base URL of the repository in the service's API.
+    fn api_url(&self, path: &FilePath) -> String;
+
+    fn path(&self) -> &str;
+
+    /// Base URL of repositories on the web, `{web_url}/{user}/{repo}` being the URL of a
+    /// repository. `None` for services that don't host git repositories.
+    fn web_url(&self) -> Option<&str> {
+        None
+    }
+
+    /// URL of a repository, that can also be used to clone it.
+    fn repo_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/{user}/{repo}", self.web_url()?))
+    }
+
+    /// Extracts user and repository from a normalized git URL, e.g. of a submodule. Returns
+    /// `None` for repositories hosted on other services.
+    fn parse_repo_url(&self, url: &str) -> Option<(String, String)> {
+        let path = url.strip_prefix(self.web_url()?)?.strip_prefix('/')?;
+        let (user, repo) = path.trim_end_matches(".git").rsplit_once('/')?;
+        (!user.is_empty() && !repo.is_empty()).then(|| (user.to_string(), repo.to_string()))
+    }
+
+    /// Route pattern matching the user segment of a path.
+    fn user_pattern(&self) -> &'static str {
+        "{user}"
+    }
+
+    /// Route patterns matching files of the service.
+    fn file_routes(&self) -> FileRoutes {
+        FileRoutes::new(self.user_pattern())
+    }
+
+    /// Adds credentials to requests against the service.
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        req
+    }
+
+    fn redirect_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("/{}/{user}/{repo}/{commit}/{file}", self.path())
+    }
+
+    /// Prepares an authorized `GET` request against the service.
+    fn get(&self, client: &Client, url: &str) -> ClientRequest {
+        self.authorize(
+            client
+                .get(url)
+                .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str())),
+        )
+    }
+
+    /// Sends a `GET` request to `url` and returns the response body.
+    async fn fetch(&self, client: &Client, url: &str) -> Result<Bytes> {
+        send(self.get(client, url)).await
+    }
+
+    /// Whether raw files are served base64 encoded. These files are decoded before being served,
+    /// so they cannot be streamed.
+    fn raw_base64(&self) -> bool {
+        false
+    }
+
+    /// Fetches a raw file from `url`, as built by `raw_url`.
+    async fn fetch_raw(&self, client: &Client, url: &str) -> Result<Bytes> {
+        let body = self.fetch(client, url).await?;
+        if self.raw_base64() {
+            decode_base64(&body)
+        } else {
+            Ok(body)
+        }
+    }
+
+    /// Like `fetch` but deserializes the response body from JSON.
+    async fn fetch_json<R: DeserializeOwned>(&self, client: &Client, url: &str) -> Result<R> {
+        let body = self.fetch(client, url).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Resolves the requested ref as a branch to the commit hash it points to.
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String>;
+
+    /// Resolves the requested ref as a tag to the commit hash it points to. Annotated tags are
+    /// peeled to the tagged commit.
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String>;
+
+    /// Fetches a paginated JSON listing. `url` builds the URL of a 1-based page, the listing
+    /// ends with the first page holding less than `page_size` entries.
+    async fn fetch_pages<R: DeserializeOwned>(
+        &self,
+        client: &Client,
+        page_size: usize,
+        url: impl Fn(usize) -> String,
+    ) -> Result<Vec<R>> {
+        let mut entries = Vec::new();
+        for page in 1.. {
+            let mut resp: Vec<R> = self.fetch_json(client, &url(page)).await?;
+            let len = resp.len();
+            entries.append(&mut resp);
+            if len < page_size {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Base URL of the repository's Git LFS server. Services that serve LFS objects from their
+    /// raw endpoint or don't support LFS at all return `None`.
+    fn lfs_url(&self, _user: &str, _repo: &str) -> Option<String> {
+        None
+    }
+
+    /// Requests the content of an LFS object through the batch API of the LFS server at
+    /// `lfs_url`.
+    async fn fetch_lfs_object(
+        &self,
+        client: &Client,
+        lfs_url: &str,
+        pointer: &Pointer,
+    ) -> Result<ClientResponse<Decompress<Payload>>> {
+        let req = self.authorize(
+            client
+                .post(format!("{lfs_url}/objects/batch"))
+                .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()))
+                .insert_header((header::ACCEPT, lfs::MEDIA_TYPE))
+                .content_type(lfs::MEDIA_TYPE),
+        );
+        let mut response = req.send_json(&BatchRequest::download(pointer)).await?;
+        if response.status() != StatusCode::OK {
+            error!(code = %response.status(), "LFS batch request failed");
+            return Err(Error::Upstream(response.status()));
+        }
+        let batch: BatchResponse = serde_json::from_slice(&response.body().await?)?;
+        let object = batch
+            .objects
+            .into_iter()
+            .next()
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))?;
+        if let Some(error) = object.error {
+            error!(code = error.code, "LFS object not available");
+            return Err(Error::Upstream(
+                StatusCode::from_u16(error.code).unwrap_or(StatusCode::BAD_GATEWAY),
+            ));
+        }
+        let download = object
+            .actions
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))?
+            .download;
+        // the storage backend is authorized by the headers of the download action
+        let mut req = client
+            .get(&download.href)
+            .insert_header((header::USER_AGENT, statics::USER_AGENT.as_str()));
+        for (name, value) in &download.header {
+            req = req.insert_header((name.as_str(), value.as_str()));
+        }
+        let response = req.send().await?;
+        match response.status() {
+            StatusCode::OK => Ok(response),
+            code => {
+                error!(code = %code, "LFS download failed");
+                Err(Error::Upstream(code))
+            }
+        }
+    }
+
+    /// Lists the directory `data.file` at the commit `data.commit`. Services without an API to
+    /// browse repositories report listings as not implemented.
+    async fn list_files(&self, _client: &Client, _data: &FilePath) -> Result<Vec<Entry>> {
+        Err(Error::Upstream(StatusCode::NOT_IMPLEMENTED))
+    }
+
+    /// URL of a gzipped tarball of the repository at the commit `data.commit`. If `data.file` is
+    /// not empty, the archive must be restricted to that directory. Services that cannot provide
+    /// such an archive return `None`, so the archive is built from individual files instead.
+    fn archive_url(&self, _data: &FilePath) -> Option<String> {
+        None
+    }
+
+    /// Builds a gzipped tarball of the directory `data.file` at the commit `data.commit` from
+    /// individual files. Symlinks and submodules are skipped.
+    async fn build_archive(&self, client: &Client, data: &FilePath) -> Result<Vec<u8>> {
+        let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut dirs = vec![data.file.to_string()];
+        let (mut listed, mut files, mut size) = (0, 0, 0);
+        while let Some(dir) = dirs.pop() {
+            listed += 1;
+            if listed > MAX_ARCHIVE_DIRS {
+                error!("too many directories for archive");
+                return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+            for entry in self
+                .list_files(client, &data.with_file(dir.clone()))
+                .await?
+            {
+                let path = if dir.is_empty() {
+                    entry.name
+                } else {
+                    format!("{dir}/{}", entry.name)
+                };
+                match entry.kind {
+                    EntryKind::Directory => dirs.push(path),
+                    EntryKind::File => {
+                        files += 1;
+                        if files > MAX_ARCHIVE_FILES {
+                            error!("too many files for archive");
+                            return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+                        }
+                        // files are skipped early if the listing reports their size
+                        if size + entry.size.unwrap_or_default() > MAX_ARCHIVE_SIZE {
+                            error!("archive too large");
+                            return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+                        }
+                        let url = self.raw_url(&data.user, &data.repo, &data.commit, &path);
+                        let content = self.fetch_raw(client, &url).await?;
+                        size += content.len() as u64;
+                        if size > MAX_ARCHIVE_SIZE {
+                            error!("archive too large");
+                            return Err(Error::Upstream(StatusCode::PAYLOAD_TOO_LARGE));
+                        }
+                        let mut header = tar::Header::new_gnu();
+                        header.set_size(content.len() as u64);
+                        header.set_mode(0o644);
+                        archive.append_data(&mut header, &path, &*content)?;
+                    }
+                    EntryKind::Symlink | EntryKind::Submodule => {}
+                }
+            }
+        }
+        Ok(archive.into_inner()?.finish()?)
+    }
+
+    /// Looks up the name of the repository's default branch.
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String>;
+
+    /// Lists all tags of the repository.
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>>;
+
+    /// Expands an abbreviated commit hash to the full hash. Services that cannot look up commits
+    /// report every commit as not found.
+    async fn expand_commit(&self, _client: &Client, _data: &FilePath) -> Result<String> {
+        Err(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    /// Resolves the requested ref to the commit hash it points to. Abbreviated commit hashes are
+    /// expanded first, then branches take precedence over tags with the same name.
+    async fn resolve_head(&self, client: &Client, data: &FilePath) -> Result<Head> {
+        if data.is_short_commit() {
+            match self.expand_commit(client, data).await {
+                Ok(commit) => return Ok(Head::new(commit, RefKind::Commit)),
+                // might still be a branch or tag that looks like a commit hash
+                Err(Error::Upstream(StatusCode::NOT_FOUND)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match self.resolve_branch(client, data).await {
+            Ok(commit) => Ok(Head::new(commit, RefKind::Branch)),
+            Err(Error::Upstream(StatusCode::NOT_FOUND)) => {
+                let commit = self.resolve_tag(client, data).await?;
+                Ok(Head::new(commit, RefKind::Tag))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Resolves the repository's default branch to the commit it points to. The name of the
+    /// default branch is cached.
+    async fn resolve_default_branch(
+        &self,
+        client: &Client,
+        data: &FilePath,
+        cache: &State,
+    ) -> Result<String> {
+        let key = data.to_key(self);
+        let branch = if let Some(branch) = cache.default_branch(&key) {
+            branch
+        } else {
+            let branch = self.default_branch(client, data).await?;
+            cache.store_default_branch(key, branch.clone());
+            branch
+        };
+        self.resolve_branch(client, &data.with_commit(branch)).await
+    }
+
+    /// Resolves a semver range to the commit of the highest matching tag. Tags may carry a `v`
+    /// prefix, tags that are no valid versions are ignored. The tag list is cached.
+    async fn resolve_range(
+        &self,
+        client: &Client,
+        data: &FilePath,
+        range: &VersionReq,
+        cache: &State,
+    ) -> Result<String> {
+        let key = data.to_repo_key(self);
+        let tags = if let Some(tags) = cache.tags(&key) {
+            tags
+        } else {
+            let tags = Arc::new(self.list_tags(client, data).await?);
+            cache.store_tags(key, Arc::clone(&tags));
+            tags
+        };
+        tags.iter()
+            .filter_map(|tag| {
+                let version =
+                    Version::parse(tag.name.strip_prefix('v').unwrap_or(&tag.name)).ok()?;
+                range.matches(&version).then_some((version, tag))
+            })
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, tag)| tag.commit.clone())
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    /// Redirects to the file at the resolved commit, cacheable for `max_age` seconds. The query
+    /// string of the request is kept.
+    fn redirect_to(&self, data: &FilePath, head: &Head, max_age: u32, query: &str) -> HttpResponse {
+        let mut location = self.redirect_url(&data.user, &data.repo, &head.commit, &data.file);
+        if !query.is_empty() {
+            location.push('?');
+            location.push_str(query);
+        }
+        HttpResponse::SeeOther()
+            .insert_header((LOCATION, location))
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(max_age),
+            ]))
+            .finish()
+    }
+
+    /// Resolves the requested ref using the service's API and caches the result.
+    async fn request_head(&self, client: &Client, data: &FilePath, cache: &State) -> Result<Head> {
+        let head = if data.is_default_branch() {
+            self.resolve_default_branch(client, data, cache)
+                .await
+                .map(|commit| Head::new(commit, RefKind::Branch))
+        } else if let Some(range) = data.version_range() {
+            // new releases move ranges, just like commits move branches
+            self.resolve_range(client, data, &range, cache)
+                .await
+                .map(|commit| Head::new(commit, RefKind::Branch))
+        } else {
+            self.resolve_head(client, data).await
+        };
+        match head {
+            Ok(head) => {
+                cache.store(data.to_key(self), &head);
+                Ok(head)
+            }
+            Err(Error::Upstream(code)) if code == StatusCode::NOT_FOUND => {
+                cache.store_missing(data.to_key(self));
+                Err(Error::Upstream(code))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Resolves the requested ref to a commit. Full commit hashes are used as is, other refs are
+    /// loaded from the cache if possible.
+    async fn head(&self, client: &Client, data: &FilePath, cache: &State) -> Result<Head> {
+        if data.is_full_commit() {
+            return Ok(Head::new(data.commit.to_string(), RefKind::Commit));
+        }
+        let key = data.to_key(self);
+        if let Some(head) = cache.get(&key) {
+            debug!("Loading HEAD from cache");
+            return head;
+        }
+        cache
+            .lookup_head(&key, async {
+                // another lookup might have finished in the meantime
+                match cache.get(&key) {
+                    Some(head) => head,
+                    None => self.request_head(client, data, cache).await,
+                }
+            })
+            .await
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Github {
+    instance: Instance,
+    auth_query: Cow<'static, str>,
+}
+
+impl Github {
+    pub(crate) fn auth_query() -> Option<Cow<'static, str>> {
+        match (
+            OPT.github_id
+                .as_ref()
+                .map(Cow::from)
+                .or_else(|| load_env_var("GITHUB_CLIENT_ID")),
+            OPT.github_secret
+                .as_ref()
+                .map(Cow::from)
+                .or_else(|| load_env_var("GITHUB_CLIENT_SECRET")),
+        ) {
+            (Some(id), Some(secret)) => {
+                Some(format!("?client_id={id}&client_secret={secret}").into())
+            }
+            _ => None,
+        }
+    }
+
+    /// Looks up a release by its tag or the most recent release.
+    async fn release(&self, client: &Client, data: &ReleasePath) -> Result<ReleaseResponse> {
+        let releases = format!(
+            "{}/repos/{}/{}/releases",
+            self.instance.api_url, data.user, data.repo
+        );
+        let url = if data.is_latest() {
+            format!("{releases}/latest{}", self.auth_query)
+        } else {
+            format!("{releases}/tags/{}{}", encode(&data.tag), self.auth_query)
+        };
+        self.fetch_json(client, &url).await
+    }
+
+    /// Looks up the tag of the most recent release.
+    pub(crate) async fn latest_release(
+        &self,
+        client: &Client,
+        data: &ReleasePath,
+    ) -> Result<String> {
+        Ok(self.release(client, data).await?.tag_name)
+    }
+
+    /// Looks up the requested asset of a release.
+    pub(crate) async fn release_asset(
+        &self,
+        client: &Client,
+        data: &ReleasePath,
+    ) -> Result<ReleaseAsset> {
+        self.release(client, data)
+            .await?
+            .assets
+            .into_iter()
+            .find(|asset| *asset.name == *data.asset)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    /// Prepares the download of a release asset. The API redirects to the storage backend.
+    pub(crate) fn download(&self, client: &Client, asset: &ReleaseAsset) -> ClientRequest {
+        self.get(client, &format!("{}{}", asset.url, self.auth_query))
+            .insert_header((header::ACCEPT, "application/octet-stream"))
+    }
+
+    pub(crate) fn release_url(&self, user: &str, repo: &str, tag: &str, asset: &str) -> String {
+        format!("/{}/{user}/{repo}/releases/{tag}/{asset}", self.path())
+    }
+
+    /// Resolves a fully qualified ref, like `refs/heads/main`, to a commit.
+    async fn resolve_ref(&self, client: &Client, data: &FilePath, name: &str) -> Result<String> {
+        let url = format!(
+            "{}/commits/{}{}",
+            self.api_url(data),
+            encode_path(name),
+            self.auth_query
+        );
+        let req = self
+            .get(client, &url)
+            .insert_header((header::ACCEPT, "application/vnd.github.3.sha"));
+        match send(req).await {
+            Ok(head) => Ok(String::from_utf8_lossy(&head).into_owned()),
+            // unknown refs are reported as `422 Unprocessable Entity`
+            Err(Error::Upstream(StatusCode::UNPROCESSABLE_ENTITY)) => {
+                Err(Error::Upstream(StatusCode::NOT_FOUND))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Default for Github {
+    fn default() -> Self {
+        Self {
+            instance: Instance::new(
+                "github",
+                "https://github.com",
+                "https://api.github.com",
+                "https://raw.githubusercontent.com",
+                data::Service::GitHub,
+            ),
+            auth_query: GITHUB_AUTH_QUERY.clone(),
+        }
+    }
+}
+
+impl From<&InstanceConfig> for Github {
+    /// GitHub Enterprise Server instance
+    fn from(config: &InstanceConfig) -> Self {
+        Self {
+            instance: Instance::from_config(config, "/api/v3", "/raw"),
+            auth_query: Cow::Borrowed(""),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Github {
+    fn cache_service(&self) -> data::Service {
+        self.instance.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.instance.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.instance.url)
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &self.instance.token {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("{}/{user}/{repo}/{commit}/{file}", self.instance.raw_url)
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!(
+            "{}/repos/{}/{}",
+            self.instance.api_url, path.user, path.repo
+        )
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file.is_empty().then(|| {
+            format!(
+                "{}/tarball/{}{}",
+                self.api_url(data),
+                data.commit,
+                self.auth_query
+            )
+        })
+    }
+
+    fn lfs_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/{user}/{repo}.git/info/lfs", self.instance.url))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.resolve_ref(client, data, &format!("refs/heads/{}", data.commit))
+            .await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.resolve_ref(client, data, &format!("refs/tags/{}", data.commit))
+            .await
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}{}", self.api_url(data), self.auth_query);
+        let resp: RepositoryResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.default_branch)
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let api_url = self.api_url(data);
+        // the auth query starts the query string, if set
+        let separator = if self.auth_query.is_empty() { '?' } else { '&' };
+        let tags: Vec<TagListResponse> = self
+            .fetch_pages(client, 100, |page| {
+                format!(
+                    "{api_url}/tags{}{separator}per_page=100&page={page}",
+                    self.auth_query
+                )
+            })
+            .await?;
+        Ok(tags.into_iter().map(Tag::from).collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let separator = if self.auth_query.is_empty() { '?' } else { '&' };
+        let url = format!(
+            "{}/contents/{}{}{separator}ref={}",
+            self.api_url(data),
+            encode_path(&data.file),
+            self.auth_query,
+            encode(&data.commit)
+        );
+        let listing: ContentsListing = self.fetch_json(client, &url).await?;
+        listing.entries()
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.resolve_ref(client, data, &data.commit).await
+    }
+}
+
+/// GitHub Gists. Revisions take the place of commits, there are no branches or tags except for
+/// the `latest` revision.
+#[derive(Clone)]
+pub(crate) struct Gist;
+
+impl Gist {
+    /// Alias for the most recent revision of a gist
+    const LATEST: &'static str = "latest";
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Gist {
+    fn cache_service(&self) -> data::Service {
+        data::Service::Gist
+    }
+
+    fn path(&self) -> &'static str {
+        "gist"
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("https://gist.github.com/{user}/{repo}/raw/{commit}/{file}")
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!("https://api.github.com/gists/{}", path.repo)
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file.is_empty().then(|| {
+            format!(
+                "https://gist.github.com/{}/{}/archive/{}.tar.gz",
+                data.user, data.repo, data.commit
+            )
+        })
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        if *data.commit != Self::LATEST {
+            return Err(Error::Upstream(StatusCode::NOT_FOUND));
+        }
+        let url = format!("{}{}", self.api_url(data), *GITHUB_AUTH_QUERY);
+        let resp: GistResponse = self.fetch_json(client, &url).await?;
+        resp.history
+            .into_iter()
+            .next()
+            .map(|revision| revision.version)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn resolve_tag(&self, _client: &Client, _data: &FilePath) -> Result<String> {
+        Err(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        // gists have no directories
+        if !data.file.is_empty() {
+            return Err(Error::Upstream(StatusCode::NOT_FOUND));
+        }
+        let url = format!(
+            "{}/{}{}",
+            self.api_url(data),
+            data.commit,
+            *GITHUB_AUTH_QUERY
+        );
+        let resp: GistRevisionResponse = self.fetch_json(client, &url).await?;
+        Ok(resp
+            .files
+            .into_iter()
+            .map(|(name, file)| Entry {
+                name,
+                size: Some(file.size),
+                kind: EntryKind::File,
+                sha: None,
+            })
+            .collect())
+    }
+
+    async fn default_branch(&self, _client: &Client, _data: &FilePath) -> Result<String> {
+        Ok(Self::LATEST.to_string())
+    }
+
+    async fn list_tags(&self, _client: &Client, _data: &FilePath) -> Result<Vec<Tag>> {
+        Ok(Vec::new())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Bitbucket;
+
+#[async_trait::async_trait(?Send)]
+impl Service for Bitbucket {
+    fn cache_service(&self) -> data::Service {
+        data::Service::Bitbucket
+    }
+
+    fn path(&self) -> &'static str {
+        "bitbucket"
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some("https://bitbucket.org")
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("https://bitbucket.org/{user}/{repo}/raw/{commit}/{file}")
+    }
+
+    fn lfs_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("https://bitbucket.org/{user}/{repo}.git/info/lfs"))
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!(
+            "https://api.bitbucket.org/2.0/repositories/{}/{}",
+            path.user, path.repo
+        )
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file.is_empty().then(|| {
+            format!(
+                "https://bitbucket.org/{}/{}/get/{}.tar.gz",
+                data.user, data.repo, data.commit
+            )
+        })
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!(
+            "{}/refs/branches/{}",
+            self.api_url(data),
+            encode(&data.commit)
+        );
+        let resp: BitbucketApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/refs/tags/{}", self.api_url(data), encode(&data.commit));
+        let resp: BitbucketApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let resp: BitbucketRepositoryResponse =
+            self.fetch_json(client, &self.api_url(data)).await?;
+        Ok(resp.mainbranch.name)
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let mut tags = Vec::new();
+        let mut next = Some(format!("{}/refs/tags?pagelen=100", self.api_url(data)));
+        while let Some(url) = next {
+            let resp: BitbucketTagListResponse = self.fetch_json(client, &url).await?;
+            tags.extend(
+                resp.values
+                    .into_iter()
+                    .map(|tag| Tag::new(tag.name, tag.target.hash)),
+            );
+            next = resp.next;
+        }
+        Ok(tags)
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let path = if data.file.is_empty() {
+            data.commit.to_string()
+        } else {
+            format!("{}/{}", data.commit, data.file)
+        };
+        let mut entries = Vec::new();
+        let mut next = Some(format!("{}/src/{path}/?pagelen=100", self.api_url(data)));
+        while let Some(url) = next {
+            let resp: BitbucketSrcResponse = self.fetch_json(client, &url).await?;
+            entries.extend(resp.values.into_iter().map(Entry::from));
+            next = resp.next;
+        }
+        Ok(entries)
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/commit/{}", self.api_url(data), data.commit);
+        let resp: BitbucketCommitResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct GitLab(Instance);
+
+impl Default for GitLab {
+    fn default() -> Self {
+        Self(Instance::new(
+            "gitlab",
+            "https://gitlab.com",
+            "https://gitlab.com/api/v4",
+            "https://gitlab.com",
+            data::Service::GitLab,
+        ))
+    }
+}
+
+impl From<&InstanceConfig> for GitLab {
+    fn from(config: &InstanceConfig) -> Self {
+        Self(Instance::from_config(config, "/api/v4", ""))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for GitLab {
+    fn cache_service(&self) -> data::Service {
+        self.0.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.0.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.0.url)
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        // unlike `PRIVATE-TOKEN`, `Authorization` is not forwarded when raw files redirect to
+        // object storage on another host
+        if let Some(token) = &self.0.token {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("{}/{user}/{repo}/raw/{commit}/{file}", self.0.raw_url)
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        let repo_pattern = format!("{}/{}", path.user, path.repo).replace('/', "%2F");
+        format!("{}/projects/{repo_pattern}", self.0.api_url)
+    }
+
+    /// GitLab restricts archives to a subdirectory on its own
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        Some(format!(
+            "{}/repository/archive.tar.gz?sha={}&path={}",
+            self.api_url(data),
+            data.commit,
+            encode(&data.file)
+        ))
+    }
+
+    fn lfs_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/{user}/{repo}.git/info/lfs", self.0.url))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!(
+            "{}/repository/branches/{}",
+            self.api_url(data),
+            encode(&data.commit)
+        );
+        let resp: GitLabApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!(
+            "{}/repository/tags/{}",
+            self.api_url(data),
+            encode(&data.commit)
+        );
+        let resp: GitLabApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let resp: RepositoryResponse = self.fetch_json(client, &self.api_url(data)).await?;
+        Ok(resp.default_branch)
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let api_url = self.api_url(data);
+        let tags: Vec<GitLabTagListResponse> = self
+            .fetch_pages(client, 100, |page| {
+                format!("{api_url}/repository/tags?per_page=100&page={page}")
+            })
+            .await?;
+        Ok(tags.into_iter().map(Tag::from).collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let api_url = self.api_url(data);
+        let entries: Vec<GitLabTreeResponse> = self
+            .fetch_pages(client, 100, |page| {
+                format!(
+                    "{api_url}/repository/tree?path={}&ref={}&per_page=100&page={page}",
+                    encode(&data.file),
+                    data.commit
+                )
+            })
+            .await?;
+        Ok(entries.into_iter().map(Entry::from).collect())
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/repository/commits/{}", self.api_url(data), data.commit);
+        let resp: GitLabCommitResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+}
+
+/// Gitea compatible service, e.g. Gitea, Forgejo or Codeberg.
+#[derive(Clone)]
+pub(crate) struct Gitea(Instance);
+
+impl Default for Gitea {
+    fn default() -> Self {
+        Self(Instance::new(
+            "gitea",
+            "https://gitea.com",
+            "https://gitea.com/api/v1",
+            "https://gitea.com",
+            data::Service::Gitea,
+        ))
+    }
+}
+
+impl Gitea {
+    pub(crate) fn codeberg() -> Self {
+        Self(Instance::new(
+            "codeberg",
+            "https://codeberg.org",
+            "https://codeberg.org/api/v1",
+            "https://codeberg.org",
+            data::Service::Codeberg,
+        ))
+    }
+}
+
+impl From<&InstanceConfig> for Gitea {
+    fn from(config: &InstanceConfig) -> Self {
+        Self(Instance::from_config(config, "/api/v1", ""))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Gitea {
+    fn cache_service(&self) -> data::Service {
+        self.0.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.0.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.0.url)
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &self.0.token {
+            req.insert_header((header::AUTHORIZATION, format!("token {token}")))
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!(
+            "{}/{user}/{repo}/raw/commit/{commit}/{file}",
+            self.0.raw_url
+        )
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!("{}/repos/{}/{}", self.0.api_url, path.user, path.repo)
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file
+            .is_empty()
+            .then(|| format!("{}/archive/{}.tar.gz", self.api_url(data), data.commit))
+    }
+
+    fn lfs_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/{user}/{repo}.git/info/lfs", self.0.url))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/branches/{}", self.api_url(data), encode(&data.commit));
+        let resp: GiteaApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/tags/{}", self.api_url(data), encode(&data.commit));
+        let resp: GiteaTagResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let resp: RepositoryResponse = self.fetch_json(client, &self.api_url(data)).await?;
+        Ok(resp.default_branch)
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let api_url = self.api_url(data);
+        // Gitea caps pages at 50 entries by default
+        let tags: Vec<TagListResponse> = self
+            .fetch_pages(client, 50, |page| {
+                format!("{api_url}/tags?limit=50&page={page}")
+            })
+            .await?;
+        Ok(tags.into_iter().map(Tag::from).collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let url = format!(
+            "{}/contents/{}?ref={}",
+            self.api_url(data),
+            data.file,
+            data.commit
+        );
+        let listing: ContentsListing = self.fetch_json(client, &url).await?;
+        listing.entries()
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/git/commits/{}", self.api_url(data), data.commit);
+        let resp: GiteaCommitResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SourceHut;
+
+impl SourceHut {
+    /// Looks up the commit of the first ref in `names` that is advertised by the repository.
+    async fn find_ref(&self, client: &Client, data: &FilePath, names: &[String]) -> Result<String> {
+        let url = format!("{}/info/refs?service=git-upload-pack", self.api_url(data));
+        let refs = self.fetch(client, &url).await?;
+        let refs: Vec<_> = git::advertised_refs(&refs).collect();
+        names
+            .iter()
+            .find_map(|name| refs.iter().find(|(_, r)| r == name))
+            .map(|(commit, _)| (*commit).to_string())
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for SourceHut {
+    fn cache_service(&self) -> data::Service {
+        data::Service::SourceHut
+    }
+
+    fn path(&self) -> &'static str {
+        "sourcehut"
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some("https://git.sr.ht")
+    }
+
+    /// `SourceHut` prefixes user names with `~`
+    fn user_pattern(&self) -> &'static str {
+        "{user:~[^/]+}"
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("https://git.sr.ht/{user}/{repo}/blob/{commit}/{file}")
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!("https://git.sr.ht/{}/{}", path.user, path.repo)
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file
+            .is_empty()
+            .then(|| format!("{}/archive/{}.tar.gz", self.api_url(data), data.commit))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, &[format!("refs/heads/{}", data.commit)])
+            .await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let tag = format!("refs/tags/{}", data.commit);
+        // annotated tags are advertised a second time, peeled to the tagged commit
+        self.find_ref(client, data, &[format!("{tag}^{{}}"), tag])
+            .await
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/info/refs?service=git-upload-pack", self.api_url(data));
+        let refs = self.fetch(client, &url).await?;
+        git::head_symref(&refs)
+            .map(str::to_string)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let url = format!("{}/info/refs?service=git-upload-pack", self.api_url(data));
+        let refs = self.fetch(client, &url).await?;
+        Ok(git::advertised_tags(&refs))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Azure;
+
+impl Azure {
+    const API_VERSION: &'static str = "7.1";
+
+    /// Looks up a fully qualified ref, like `refs/heads/main`, and returns the commit it points
+    /// to.
+    async fn find_ref(&self, client: &Client, data: &FilePath, name: &str) -> Result<String> {
+        let url = format!(
+            "{}/refs?filter={}&peelTags=true&api-version={}",
+            self.api_url(data),
+            encode(name.trim_start_matches("refs/")),
+            Self::API_VERSION,
+        );
+        let resp: AzureApiResponse = self.fetch_json(client, &url).await?;
+        // `filter` matches by prefix, so `main` would also match `main-old`
+        resp.value
+            .into_iter()
+            .find(|r| r.name == name)
+            .map(|r| r.peeled_object_id.unwrap_or(r.object_id))
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Azure {
+    fn cache_service(&self) -> data::Service {
+        data::Service::Azure
+    }
+
+    fn path(&self) -> &'static str {
+        "azure"
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some("https://dev.azure.com")
+    }
+
+    fn repo_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("https://dev.azure.com/{user}/_git/{repo}"))
+    }
+
+    fn parse_repo_url(&self, url: &str) -> Option<(String, String)> {
+        let path = url.strip_prefix("https://dev.azure.com/")?;
+        let (user, repo) = path.split_once("/_git/")?;
+        // the user consists of organization and project
+        (user.split('/').count() == 2 && !repo.contains('/'))
+            .then(|| (user.to_string(), repo.trim_end_matches(".git").to_string()))
+    }
+
+    /// Repositories are owned by a project inside an organization
+    fn user_pattern(&self) -> &'static str {
+        "{user:[^/]+/[^/]+}"
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &*AZURE_TOKEN {
+            req.basic_auth("", token)
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!(
+            "https://dev.azure.com/{user}/_apis/git/repositories/{repo}/items?path={}&versionDescriptor.version={commit}&versionDescriptor.versionType=commit&resolveLfs=true&$format=octetStream&api-version={}",
+            encode(file),
+            Self::API_VERSION,
+        )
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!(
+            "https://dev.azure.com/{}/_apis/git/repositories/{}",
+            path.user, path.repo,
+        )
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, &format!("refs/heads/{}", data.commit))
+            .await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, &format!("refs/tags/{}", data.commit))
+            .await
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}?api-version={}", self.api_url(data), Self::API_VERSION);
+        let resp: AzureRepositoryResponse = self.fetch_json(client, &url).await?;
+        resp.default_branch
+            .as_deref()
+            .and_then(|branch| branch.strip_prefix("refs/heads/"))
+            .map(str::to_string)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let url = format!(
+            "{}/refs?filter=tags/&peelTags=true&api-version={}",
+            self.api_url(data),
+            Self::API_VERSION,
+        );
+        let resp: AzureApiResponse = self.fetch_json(client, &url).await?;
+        Ok(resp
+            .value
+            .into_iter()
+            .filter_map(|r| {
+                let name = r.name.strip_prefix("refs/tags/")?.to_string();
+                Some(Tag::new(name, r.peeled_object_id.unwrap_or(r.object_id)))
+            })
+            .collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let scope = format!("/{}", data.file);
+        let url = format!(
+            "{}/items?scopePath={}&recursionLevel=OneLevel&versionDescriptor.version={}&versionDescriptor.versionType=commit&api-version={}",
+            self.api_url(data),
+            encode(&scope),
+            data.commit,
+            Self::API_VERSION,
+        );
+        let resp: AzureItemsResponse = self.fetch_json(client, &url).await?;
+        Ok(resp
+            .value
+            .into_iter()
+            // the listed directory is part of the response
+            .filter(|item| item.path != scope)
+            .map(Entry::from)
+            .collect())
+    }
+}
+
+/// Bitbucket Server or Data Center instance. Users are project keys, or `~user` for personal
+/// repositories.
+#[derive(Clone)]
+pub(crate) struct BitbucketServer(Instance);
+
+impl From<&InstanceConfig> for BitbucketServer {
+    fn from(config: &InstanceConfig) -> Self {
+        // raw files are served by the API
+        Self(Instance::from_config(
+            config,
+            "/rest/api/1.0",
+            "/rest/api/1.0",
+        ))
+    }
+}
+
+impl BitbucketServer {
+    /// Fetches all pages of a paginated listing. `url` must already contain a query.
+    async fn fetch_all<R: DeserializeOwned>(&self, client: &Client, url: &str) -> Result<Vec<R>> {
+        let mut values = Vec::new();
+        let mut start = 0;
+        loop {
+            let page: BitbucketServerPage<R> = self
+                .fetch_json(client, &format!("{url}&start={start}&limit=100"))
+                .await?;
+            values.extend(page.values);
+            match page.next_page_start {
+                Some(next) if !page.is_last_page => start = next,
+                _ => return Ok(values),
+            }
+        }
+    }
+
+    /// Looks up the branch or tag named like the requested ref. `kind` is either `branches` or
+    /// `tags`.
+    async fn find_ref(&self, client: &Client, data: &FilePath, kind: &str) -> Result<String> {
+        let url = format!(
+            "{}/{kind}?filterText={}",
+            self.api_url(data),
+            encode(&data.commit)
+        );
+        // the filter matches substrings
+        self.fetch_all::<BitbucketServerRef>(client, &url)
+            .await?
+            .into_iter()
+            .find(|r| *r.display_id == *data.commit)
+            .map(|r| r.latest_commit)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for BitbucketServer {
+    fn cache_service(&self) -> data::Service {
+        self.0.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.0.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.0.url)
+    }
+
+    /// Repositories are cloned from `/scm`
+    fn repo_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/scm/{user}/{repo}", self.0.url))
+    }
+
+    fn parse_repo_url(&self, url: &str) -> Option<(String, String)> {
+        let path = url.strip_prefix(&*self.0.url)?.strip_prefix("/scm/")?;
+        let (user, repo) = path.trim_end_matches(".git").split_once('/')?;
+        (!repo.contains('/')).then(|| (user.to_string(), repo.to_string()))
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &self.0.token {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!(
+            "{}/projects/{user}/repos/{repo}/raw/{file}?at={commit}",
+            self.0.raw_url
+        )
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!(
+            "{}/projects/{}/repos/{}",
+            self.0.api_url, path.user, path.repo
+        )
+    }
+
+    fn lfs_url(&self, user: &str, repo: &str) -> Option<String> {
+        Some(format!("{}/scm/{user}/{repo}.git/info/lfs", self.0.url))
+    }
+
+    /// Bitbucket Server restricts archives to a subdirectory on its own
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        Some(format!(
+            "{}/archive?at={}&format=tar.gz&path={}",
+            self.api_url(data),
+            data.commit,
+            encode(&data.file)
+        ))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, "branches").await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, "tags").await
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/commits/{}", self.api_url(data), data.commit);
+        let resp: BitbucketServerCommitResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/default-branch", self.api_url(data));
+        let resp: BitbucketServerDefaultBranchResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.display_id)
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let url = format!("{}/tags?orderBy=MODIFICATION", self.api_url(data));
+        Ok(self
+            .fetch_all::<BitbucketServerRef>(client, &url)
+            .await?
+            .into_iter()
+            .map(|tag| Tag::new(tag.display_id, tag.latest_commit))
+            .collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut start = 0;
+        loop {
+            let url = format!(
+                "{}/browse/{}?at={}&start={start}&limit=500",
+                self.api_url(data),
+                data.file,
+                data.commit
+            );
+            let resp: BitbucketServerBrowseResponse = self.fetch_json(client, &url).await?;
+            let page = resp.children;
+            entries.extend(page.values.into_iter().map(Entry::from));
+            match page.next_page_start {
+                Some(next) if !page.is_last_page => start = next,
+                _ => return Ok(entries),
+            }
+        }
+    }
+}
+
+/// Gitiles instance, the repository browser used by Gerrit and `googlesource.com`. Repositories
+/// nested deeper than `user/repo` can be served by including the prefix in the instance URL.
+#[derive(Clone)]
+pub(crate) struct Gitiles(Instance);
+
+impl From<&InstanceConfig> for Gitiles {
+    fn from(config: &InstanceConfig) -> Self {
+        Self(Instance::from_config(config, "", ""))
+    }
+}
+
+impl Gitiles {
+    /// Prefix protecting JSON responses against XSSI
+    const XSSI_PREFIX: &'static [u8] = b")]}'";
+
+    /// Deserializes a JSON response.
+    fn parse_json<R: DeserializeOwned>(body: &[u8]) -> Result<R> {
+        let body = body.strip_prefix(Self::XSSI_PREFIX).unwrap_or(body);
+        Ok(serde_json::from_slice(body)?)
+    }
+
+    /// Fetches a JSON view, e.g. `+refs`, of a repository.
+    async fn fetch_view<R: DeserializeOwned>(
+        &self,
+        client: &Client,
+        data: &FilePath,
+        view: &str,
+    ) -> Result<R> {
+        let url = format!("{}/{view}?format=JSON", self.api_url(data));
+        Self::parse_json(&self.fetch(client, &url).await?)
+    }
+
+    /// Lists the refs below `refs/{kind}/`, keyed by their name without prefix.
+    async fn refs(
+        &self,
+        client: &Client,
+        data: &FilePath,
+        kind: &str,
+    ) -> Result<HashMap<String, GitilesRef>> {
+        self.fetch_view(client, data, &format!("+refs/{kind}"))
+            .await
+    }
+
+    /// Looks up a single ref below `refs/{kind}/`. An exact match is keyed by its full name.
+    async fn find_ref(&self, client: &Client, data: &FilePath, kind: &str) -> Result<String> {
+        let name = format!("refs/{kind}/{}", data.commit);
+        let view = format!("+{}", encode_path(&name));
+        self.fetch_view::<HashMap<String, GitilesRef>>(client, data, &view)
+            .await?
+            .remove(&name)
+            .map(GitilesRef::commit)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Gitiles {
+    fn cache_service(&self) -> data::Service {
+        self.0.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.0.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.0.url)
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &self.0.token {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+
+    /// Gitiles serves raw files base64 encoded
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!(
+            "{}/{user}/{repo}/+/{commit}/{file}?format=TEXT",
+            self.0.raw_url
+        )
+    }
+
+    fn raw_base64(&self) -> bool {
+        true
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!("{}/{}/{}", self.0.api_url, path.user, path.repo)
+    }
+
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        let path = if data.file.is_empty() {
+            data.commit.to_string()
+        } else {
+            format!("{}/{}", data.commit, data.file)
+        };
+        Some(format!("{}/+archive/{path}.tar.gz", self.api_url(data)))
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, "heads").await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, "tags").await
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let resp: GitilesCommitResponse = self
+            .fetch_view(client, data, &format!("+/{}", data.commit))
+            .await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let mut refs: HashMap<String, GitilesRef> = self.fetch_view(client, data, "+refs").await?;
+        refs.remove("HEAD")
+            .and_then(|head| head.target)
+            .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string))
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        Ok(self
+            .refs(client, data, "tags")
+            .await?
+            .into_iter()
+            .map(|(name, tag)| Tag::new(name, tag.commit()))
+            .collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        // `long` adds the size of files
+        let url = format!(
+            "{}/+/{}/{}?format=JSON&long=1",
+            self.api_url(data),
+            data.commit,
+            data.file
+        );
+        let resp: GitilesTreeResponse = Self::parse_json(&self.fetch(client, &url).await?)?;
+        Ok(resp.entries.into_iter().map(Entry::from).collect())
+    }
+}
+
+/// cgit instance. Refs are read from the files served to dumb HTTP clients, so these must not be
+/// disabled. Repositories nested deeper than `user/repo` can be served by including the prefix in
+/// the instance URL.
+#[derive(Clone)]
+pub(crate) struct Cgit(Instance);
+
+impl From<&InstanceConfig> for Cgit {
+    fn from(config: &InstanceConfig) -> Self {
+        Self(Instance::from_config(config, "", ""))
+    }
+}
+
+impl Cgit {
+    /// Looks up the commit of the first ref in `names` that is listed in `info/refs`. The file is
+    /// scanned while it is downloaded, so the refs of large repositories are never buffered.
+    async fn find_ref(&self, client: &Client, data: &FilePath, names: &[String]) -> Result<String> {
+        let url = format!("{}/info/refs", self.api_url(data));
+        let mut response = self.get(client, &url).send().await?;
+        if response.status() != StatusCode::OK {
+            error!(code = %response.status(), "request failed");
+            return Err(Error::Upstream(response.status()));
+        }
+        let mut found = vec![None; names.len()];
+        let mut pending = Vec::new();
+        loop {
+            let chunk = response.next().await.transpose()?;
+            // only complete lines are parsed, unless the file has ended
+            let end = match &chunk {
+                Some(chunk) => {
+                    pending.extend_from_slice(chunk);
+                    pending
+                        .iter()
+                        .rposition(|&b| b == b'\n')
+                        .map_or(0, |i| i + 1)
+                }
+                None => pending.len(),
+            };
+            for (commit, name) in git::dumb_refs(&pending[..end]) {
+                if let Some(i) = names.iter().position(|n| n == name) {
+                    found[i].get_or_insert_with(|| commit.to_string());
+                }
+            }
+            pending.drain(..end);
+            // the first name takes precedence over the others
+            if chunk.is_none() || found[0].is_some() {
+                break;
+            }
+        }
+        found
+            .into_iter()
+            .flatten()
+            .next()
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for Cgit {
+    fn cache_service(&self) -> data::Service {
+        self.0.cache_service.clone()
+    }
+
+    fn path(&self) -> &str {
+        &self.0.name
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some(&self.0.url)
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("{}/{user}/{repo}/plain/{file}?id={commit}", self.0.raw_url)
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        format!("{}/{}/{}", self.0.api_url, path.user, path.repo)
+    }
+
+    /// Snapshots are named after the repository, they must be enabled for the instance
+    fn archive_url(&self, data: &FilePath) -> Option<String> {
+        data.file.is_empty().then(|| {
+            format!(
+                "{}/snapshot/{}-{}.tar.gz",
+                self.api_url(data),
+                data.repo.trim_end_matches(".git"),
+                data.commit
+            )
+        })
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        self.find_ref(client, data, &[format!("refs/heads/{}", data.commit)])
+            .await
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let tag = format!("refs/tags/{}", data.commit);
+        // annotated tags are listed a second time, peeled to the tagged commit
+        self.find_ref(client, data, &[format!("{tag}^{{}}"), tag])
+            .await
+    }
+
+    /// The patch of a commit starts with `From <commit>`
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/patch/?id={}", self.api_url(data), data.commit);
+        let patch = self.fetch(client, &url).await?;
+        std::str::from_utf8(&patch)
+            .ok()
+            .and_then(|patch| patch.strip_prefix("From ")?.split_whitespace().next())
+            .filter(|commit| commit.starts_with(&*data.commit))
+            .map(str::to_string)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn default_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/HEAD", self.api_url(data));
+        let head = self.fetch(client, &url).await?;
+        git::dumb_head(&head)
+            .map(str::to_string)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        let url = format!("{}/info/refs", self.api_url(data));
+        let refs = self.fetch(client, &url).await?;
+        Ok(git::tags(git::dumb_refs(&refs)))
+    }
+}
+
+/// Hugging Face Hub. Models are owned by `{user}`, datasets and spaces by `datasets/{user}` and
+/// `spaces/{user}`, just like on the Hub.
+#[derive(Clone)]
+pub(crate) struct HuggingFace;
+
+impl HuggingFace {
+    /// Looks up the refs of a repository.
+    async fn refs(&self, client: &Client, data: &FilePath) -> Result<HuggingFaceRefsResponse> {
+        self.fetch_json(client, &format!("{}/refs", self.api_url(data)))
+            .await
+    }
+
+    /// Looks up the commit of the ref named like the requested ref.
+    fn find_ref(refs: Vec<HuggingFaceRef>, data: &FilePath) -> Result<String> {
+        refs.into_iter()
+            .find(|r| *r.name == *data.commit)
+            .map(|r| r.target_commit)
+            .ok_or(Error::Upstream(StatusCode::NOT_FOUND))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Service for HuggingFace {
+    fn cache_service(&self) -> data::Service {
+        data::Service::HuggingFace
+    }
+
+    fn path(&self) -> &'static str {
+        "huggingface"
+    }
+
+    fn web_url(&self) -> Option<&str> {
+        Some("https://huggingface.co")
+    }
+
+    /// Datasets and spaces are prefixed with their repository type
+    fn user_pattern(&self) -> &'static str {
+        "{user:(?:datasets/|spaces/)?[^/]+}"
+    }
+
+    fn authorize(&self, req: ClientRequest) -> ClientRequest {
+        if let Some(token) = &*HF_TOKEN {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+
+    fn raw_url(&self, user: &str, repo: &str, commit: &str, file: &str) -> String {
+        format!("https://huggingface.co/{user}/{repo}/resolve/{commit}/{file}")
+    }
+
+    fn api_url(&self, path: &FilePath) -> String {
+        let (kind, user) = if let Some(user) = path.user.strip_prefix("datasets/") {
+            ("datasets", user)
+        } else if let Some(user) = path.user.strip_prefix("spaces/") {
+            ("spaces", user)
+        } else {
+            ("models", path.user.as_str())
+        };
+        format!("https://huggingface.co/api/{kind}/{user}/{}", path.repo)
+    }
+
+    async fn resolve_branch(&self, client: &Client, data: &FilePath) -> Result<String> {
+        Self::find_ref(self.refs(client, data).await?.branches, data)
+    }
+
+    async fn resolve_tag(&self, client: &Client, data: &FilePath) -> Result<String> {
+        Self::find_ref(self.refs(client, data).await?.tags, data)
+    }
+
+    async fn expand_commit(&self, client: &Client, data: &FilePath) -> Result<String> {
+        let url = format!("{}/revision/{}", self.api_url(data), data.commit);
+        let resp: HuggingFaceRevisionResponse = self.fetch_json(client, &url).await?;
+        Ok(resp.commit_ref().to_string())
+    }
+
+    async fn default_branch(&self, _client: &Client, _data: &FilePath) -> Result<String> {
+        // the Hub does not allow to change the default branch
+        Ok("main".to_string())
+    }
+
+    async fn list_tags(&self, client: &Client, data: &FilePath) -> Result<Vec<Tag>> {
+        Ok(self
+            .refs(client, data)
+            .await?
+            .tags
+            .into_iter()
+            .map(|tag| Tag::new(tag.name, tag.target_commit))
+            .collect())
+    }
+
+    async fn list_files(&self, client: &Client, data: &FilePath) -> Result<Vec<Entry>> {
+        let url = format!("{}/tree/{}/{}", self.api_url(data), data.commit, data.file);
+        let entries: Vec<HuggingFaceTreeResponse> = self.fetch_json(client, &url).await?;
+        Ok(entries.into_iter().map(Entry::from).collect())
+    }
+}
+
+/// Any of the supported services. Used to look up services by their path, e.g. for requests that
+/// span multiple services.
+#[derive(Clone)]
+pub(crate) enum AnyService {
+    Github(Github),
+    Gist(Gist),
+    Bitbucket(Bitbucket),
+    GitLab(GitLab),
+    Gitea(Gitea),
+    SourceHut(SourceHut),
+    Azure(Azure),
+    HuggingFace(HuggingFace),
+    BitbucketServer(BitbucketServer),
+    Gitiles(Gitiles),
+    Cgit(Cgit),
+}
+
+/// Evaluates `$body` with `$service` bound to the service wrapped by the `AnyService` `$any`.
+macro_rules! with_service {
+    ($any:expr, $service:ident => $body:expr) => {
+        match $any {
+            AnyService::Github($service) => $body,
+            AnyService::Gist($service) => $body,
+            AnyService::Bitbucket($service) => $body,
+            AnyService::GitLab($service) => $body,
+            AnyService::Gitea($service) => $body,
+            AnyService::SourceHut($service) => $body,
+            AnyService::Azure($service) => $body,
+            AnyService::HuggingFace($service) => $body,
+            AnyService::BitbucketServer($service) => $body,
+            AnyService::Gitiles($service) => $body,
+            AnyService::Cgit($service) => $body,
+        }
+    };
+}
+pub(crate) use with_service;
+
+impl AnyService {
+    pub(crate) fn path(&self) -> &str {
+        with_service!(self, service => service.path())
+    }
+
+    pub(crate) fn parse_repo_url(&self, url: &str) -> Option<(String, String)> {
+        with_service!(self, service => service.parse_repo_url(url))
+    }
+}
+
+impl From<&InstanceConfig> for AnyService {
+    fn from(config: &InstanceConfig) -> Self {
+        match config.kind {
+            InstanceKind::Github => AnyService::Github(Github::from(config)),
+            InstanceKind::Gitlab => AnyService::GitLab(GitLab::from(config)),
+            InstanceKind::Gitea => AnyService::Gitea(Gitea::from(config)),
+            InstanceKind::BitbucketServer => {
+                AnyService::BitbucketServer(BitbucketServer::from(config))
+            }
+            InstanceKind::Gitiles => AnyService::Gitiles(Gitiles::from(config)),
+            InstanceKind::Cgit => AnyService::Cgit(Cgit::from(config)),
+        }
+    }
+}
+
+/// The built-in services and configured instances.
+#[derive(Clone)]
+pub(crate) struct Services(Vec<AnyService>);
+
+impl Services {
+    pub(crate) fn new(instances: &Instances) -> Self {
+        let mut services = vec![
+            AnyService::Github(Github::default()),
+            AnyService::Bitbucket(Bitbucket),
+            AnyService::GitLab(GitLab::default()),
+            AnyService::Gitea(Gitea::default()),
+            AnyService::Gitea(Gitea::codeberg()),
+            AnyService::SourceHut(SourceHut),
+            AnyService::Azure(Azure),
+            AnyService::Gist(Gist),
+            AnyService::HuggingFace(HuggingFace),
+        ];
+        services.extend(instances.instance.iter().map(AnyService::from));
+        Self(services)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &AnyService> {
+        self.0.iter()
+    }
+
+    /// Looks up the service hosting the repository at `url` and returns it along with the user
+    /// and repository.
+    pub(crate) fn find_repo(&self, url: &str) -> Option<(&AnyService, String, String)> {
+        self.0.iter().find_map(|service| {
+            let (user, repo) = service.parse_repo_url(url)?;
+            Some((service, user, repo))
+        })
+    }
+
+    /// Looks up a service by its path.
+    pub(crate) fn find(&self, path: &str) -> Option<&AnyService> {
+        self.0.iter().find(|service| service.path() == path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_base64, encode_path, ContentsListing, Gitiles, GitilesCommitResponse, GitilesRef,
+    };
+    use crate::{data::EntryKind, error::Error};
+    use awc::http::StatusCode;
+    use std::collections::HashMap;
+
+    #[test]
+    fn contents_listing_of_directory() {
+        let body = r#"[
+            {"name": "a.js", "size": 3, "type": "file", "sha": "1111", "download_url": "u"},
+            {"name": "lib", "size": 0, "type": "file", "sha": "2222", "download_url": null}
+        ]"#;
+        let listing: ContentsListing = serde_json::from_str(body).unwrap();
+        let entries = listing.entries().unwrap();
+        assert!(matches!(entries[0].kind, EntryKind::File));
+        assert_eq!(Some(3), entries[0].size);
+        // GitHub lists submodules as files without a download URL
+        assert!(matches!(entries[1].kind, EntryKind::Submodule));
+    }
+
+    #[test]
+    fn contents_listing_of_file() {
+        let body = r#"{"name": "a.js", "size": 3, "type": "file", "sha": "1111", "content": ""}"#;
+        let listing: ContentsListing = serde_json::from_str(body).unwrap();
+        assert!(matches!(
+            listing.entries(),
+            Err(Error::Upstream(StatusCode::BAD_REQUEST))
+        ));
+    }
+
+    #[test]
+    fn gitiles_strips_xssi_prefix() {
+        let body = b")]}'\n{\"commit\": \"0123456789abcdef0123456789abcdef01234567\"}";
+        let response: GitilesCommitResponse = Gitiles::parse_json(body).unwrap();
+        assert_eq!("0123456789abcdef0123456789abcdef01234567", response.commit);
+    }
+
+    #[test]
+    fn gitiles_parses_json_without_prefix() {
+        let body = br#"{"refs/tags/v1": {"value": "1111", "peeled": "2222"}}"#;
+        let mut refs: HashMap<String, GitilesRef> = Gitiles::parse_json(body).unwrap();
+        assert_eq!("2222", refs.remove("refs/tags/v1").unwrap().commit());
+    }
+
+    #[test]
+    fn encode_path_keeps_slashes() {
+        assert_eq!(
+            "refs/heads/release/1.2%231%3F%25%20x",
+            encode_path("refs/heads/release/1.2#1?% x")
+        );
+    }
+
+    #[test]
+    fn decode_base64_ignores_line_breaks() {
+        assert_eq!(
+            &b"hello world"[..],
+            decode_base64(b"aGVsbG8g\nd29y\r\nbGQ=\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_base64_rejects_invalid_input() {
+        assert!(decode_base64(b"not base64!").is_err());
     }
 }
@@ -0,0 +1,136 @@
+//! Coalescing of concurrent lookups, so only one request per key hits the upstream API.
+
+use tokio::sync::watch;
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+};
+
+/// Lookups that are currently running, keyed by what they look up. Waiters subscribe to the
+/// result of the running lookup.
+pub(crate) struct SingleFlight<K, V> {
+    flights: Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+}
+
+/// Role of a caller for a key.
+enum Role<V> {
+    /// Runs the lookup and publishes its result
+    Leader(watch::Sender<Option<V>>),
+    /// Waits for the result of the leader
+    Waiter(watch::Receiver<Option<V>>),
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `lookup` unless a lookup for `key` is already running, in which case its result is
+    /// shared. If the running lookup is cancelled, one of the waiters takes over.
+    pub(crate) async fn run(&self, key: &K, lookup: impl Future<Output = V>) -> V {
+        let sender = loop {
+            match self.join(key) {
+                Role::Leader(sender) => break sender,
+                Role::Waiter(mut receiver) => {
+                    if let Ok(value) = receiver.wait_for(Option::is_some).await {
+                        if let Some(value) = &*value {
+                            return value.clone();
+                        }
+                    }
+                    // the leader was dropped before finishing the lookup
+                }
+            }
+        };
+        let _flight = Flight { flights: self, key };
+        let value = lookup.await;
+        // waiters might have given up already
+        let _ = sender.send(Some(value.clone()));
+        value
+    }
+
+    fn join(&self, key: &K) -> Role<V> {
+        let mut flights = self.flights.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(receiver) = flights.get(key) {
+            Role::Waiter(receiver.clone())
+        } else {
+            let (sender, receiver) = watch::channel(None);
+            flights.insert(key.clone(), receiver);
+            Role::Leader(sender)
+        }
+    }
+}
+
+/// Removes a finished or cancelled lookup, so the next caller starts a new one.
+struct Flight<'a, K: Eq + Hash, V> {
+    flights: &'a SingleFlight<K, V>,
+    key: &'a K,
+}
+
+impl<K: Eq + Hash, V> Drop for Flight<'_, K, V> {
+    fn drop(&mut self) {
+        self.flights
+            .flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SingleFlight;
+    use futures_util::future::{join, join3, poll_immediate};
+    use tokio::sync::oneshot;
+
+    use std::{cell::Cell, future::pending};
+
+    #[actix_web::test]
+    async fn waiters_get_result_of_leader() {
+        let flights = SingleFlight::new();
+        let lookups = Cell::new(0);
+        let (sender, receiver) = oneshot::channel();
+        let leader = flights.run(&0, async {
+            lookups.set(lookups.get() + 1);
+            receiver.await.unwrap()
+        });
+        let waiter = flights.run(&0, async {
+            lookups.set(lookups.get() + 1);
+            2
+        });
+        let (leader, waiter, ()) = join3(leader, waiter, async {
+            sender.send(1).unwrap();
+        })
+        .await;
+        assert_eq!((1, 1), (leader, waiter));
+        assert_eq!(1, lookups.get());
+        assert!(flights.flights.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn waiter_takes_over_cancelled_lookup() {
+        let flights = SingleFlight::new();
+        let mut leader = Box::pin(flights.run(&0, pending::<u8>()));
+        assert!(poll_immediate(&mut leader).await.is_none());
+        let waiter = flights.run(&0, async { 2 });
+        let (waiter, ()) = join(waiter, async { drop(leader) }).await;
+        assert_eq!(2, waiter);
+        assert!(flights.flights.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn different_keys_run_separately() {
+        let flights = SingleFlight::new();
+        let (first, second) =
+            join(flights.run(&0, async { 1 }), flights.run(&1, async { 2 })).await;
+        assert_eq!((1, 2), (first, second));
+    }
+}
@@ -6,13 +6,51 @@ use std::{borrow::Cow, env, sync::LazyLock, time::Duration};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) const REDIRECT_AGE: Duration = Duration::from_mins(5);
+pub(crate) const TAG_AGE: Duration = Duration::from_hours(24);
+/// New tags should be picked up by version ranges as fast as branch updates
+pub(crate) const TAG_LIST_AGE: Duration = REDIRECT_AGE;
+/// Default branches are rarely changed
+pub(crate) const DEFAULT_BRANCH_AGE: Duration = Duration::from_hours(1);
+/// Expired heads are still served for redirects while they are refreshed, or if the service is
+/// unavailable
+pub(crate) const STALE_AGE: Duration = Duration::from_hours(24);
+/// Stale heads are refreshed at most once in this time, so an unavailable service isn't hit on
+/// every request
+pub(crate) const STALE_RETRY_AGE: Duration = Duration::from_secs(30);
+/// Refs that don't exist are cached shortly, so they don't hit the API on every request
+pub(crate) const MISSING_AGE: Duration = Duration::from_secs(30);
+/// Abbreviated commit hashes are practically immutable
+pub(crate) const COMMIT_AGE: Duration = Duration::from_hours(24 * 30);
+/// Maximum number of entries per cache, least recently used entries are evicted
+pub(crate) const CACHE_CAPACITY: usize = 10_000;
+/// Minified files are larger than refs, so fewer of them are cached
+pub(crate) const MINIFIED_CAPACITY: usize = 1_000;
+/// `max-age` for responses that never change, like files at a specific commit
+pub(crate) const IMMUTABLE_AGE_SECS: u32 = 2_592_000_000;
 pub(crate) const FAVICON: &[u8] = include_bytes!("../static/favicon32.png");
 pub(crate) static REDIRECT_AGE_SECS: LazyLock<u32> =
     LazyLock::new(|| u32::try_from(REDIRECT_AGE.as_secs()).expect("redirect age to high"));
+/// `max-age` for redirects to stale heads, so caches pick up the refreshed head soon
+pub(crate) static STALE_RETRY_AGE_SECS: LazyLock<u32> =
+    LazyLock::new(|| u32::try_from(STALE_RETRY_AGE.as_secs()).expect("stale retry age to high"));
+pub(crate) static TAG_AGE_SECS: LazyLock<u32> =
+    LazyLock::new(|| u32::try_from(TAG_AGE.as_secs()).expect("tag age to high"));
 pub(crate) static USER_AGENT: LazyLock<String> = LazyLock::new(|| format!("yagcdn/{VERSION}"));
 pub(crate) static OPT: LazyLock<Opt> = LazyLock::new(Opt::parse);
 pub(crate) static GITHUB_AUTH_QUERY: LazyLock<Cow<'static, str>> =
     LazyLock::new(|| Github::auth_query().unwrap_or_default());
+pub(crate) static AZURE_TOKEN: LazyLock<Option<Cow<'static, str>>> = LazyLock::new(|| {
+    OPT.azure_token
+        .as_ref()
+        .map(Cow::from)
+        .or_else(|| load_env_var("AZURE_DEVOPS_TOKEN"))
+});
+pub(crate) static HF_TOKEN: LazyLock<Option<Cow<'static, str>>> = LazyLock::new(|| {
+    OPT.hf_token
+        .as_ref()
+        .map(Cow::from)
+        .or_else(|| load_env_var("HF_TOKEN"))
+});
 pub(crate) static CF_ZONE_IDENT: LazyLock<Cow<'static, str>> = LazyLock::new(|| {
     OPT.cf_zone
         .as_ref()
@@ -42,6 +80,13 @@ pub(crate) static HOSTNAME: LazyLock<Cow<'static, str>> = LazyLock::new(|| {
         .unwrap_or_else(|| "yagcdn.tk".into())
 });
 
+pub(crate) static INSTANCES_PATH: LazyLock<Option<Cow<'static, str>>> = LazyLock::new(|| {
+    OPT.instances
+        .as_ref()
+        .map(Cow::from)
+        .or_else(|| load_env_var("YAGCDN_INSTANCES"))
+});
+
 pub(crate) fn load_env_var(key: &str) -> Option<Cow<'static, str>> {
     env::var(key).ok().and_then(|val| {
         if val.is_empty() {
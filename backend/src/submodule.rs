@@ -0,0 +1,114 @@
+//! Resolution of paths that cross into git submodules.
+
+use crate::{
+    data::{EntryKind, FilePath, State},
+    error::Error,
+    git,
+    service::{with_service, AnyService, Service, Services},
+    statics::IMMUTABLE_AGE_SECS,
+};
+
+use actix_web::{
+    http::{
+        header::{CacheControl, CacheDirective, LOCATION},
+        StatusCode,
+    },
+    HttpResponse,
+};
+use awc::Client;
+use tracing::{debug, info};
+
+use std::sync::Arc;
+
+/// Redirects requests for files inside a submodule to the file in the submodule's repository, at
+/// the commit pinned by the requested commit. Returns `None` if the file is not inside a
+/// submodule, or the submodule cannot be resolved.
+pub(crate) async fn resolve<T: Service>(
+    service: &T,
+    services: &Services,
+    cache: &State,
+    client: &Client,
+    data: &FilePath,
+) -> Option<HttpResponse> {
+    // files in the root directory cannot be inside a submodule
+    if !data.file.contains('/') {
+        return None;
+    }
+    let submodules = submodules(service, cache, client, data).await?;
+    // nested submodules share a path prefix, the innermost one contains the file
+    let (path, url, file) = submodules
+        .iter()
+        .filter_map(|(path, url)| {
+            let file = data.file.strip_prefix(path)?.strip_prefix('/')?;
+            Some((path.as_str(), url.as_str(), file))
+        })
+        .max_by_key(|(path, _, _)| path.len())?;
+    debug!(submodule = %path, "file is inside a submodule");
+
+    // the tree entry of a submodule points to the pinned commit
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let entry = service
+        .list_files(client, &data.with_file(parent.to_string()))
+        .await
+        .ok()?
+        .into_iter()
+        .find(|entry| matches!(entry.kind, EntryKind::Submodule) && entry.name == name)?;
+    let Some(commit) = entry.sha else {
+        info!(submodule = %path, "service doesn't report the pinned commit");
+        return Some(HttpResponse::NotFound().body(format!(
+            "the commit of submodule `{path}` cannot be resolved on {}",
+            service.path()
+        )));
+    };
+
+    let repo_url = service.repo_url(&data.user, &data.repo).unwrap_or_default();
+    let Some((target, user, repo)) = git::normalize_url(url, &repo_url)
+        .as_deref()
+        .and_then(|url| services.find_repo(url))
+    else {
+        info!(submodule = %path, url = %url, "submodule hosted on unsupported service");
+        return Some(HttpResponse::NotFound().body(format!(
+            "submodule `{path}` is hosted on an unsupported service: {url}"
+        )));
+    };
+    info!(submodule = %path, service = target.path(), "resolved submodule");
+    Some(
+        HttpResponse::SeeOther()
+            .insert_header((
+                LOCATION,
+                with_service!(target, target => target.redirect_url(&user, &repo, &commit, file)),
+            ))
+            // the requested commit pins the submodule
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(IMMUTABLE_AGE_SECS),
+            ]))
+            .finish(),
+    )
+}
+
+/// Loads the submodules declared at the requested commit. Commits never change, so `.gitmodules`
+/// is fetched only once per commit, even if it doesn't exist.
+async fn submodules<T: Service>(
+    service: &T,
+    cache: &State,
+    client: &Client,
+    data: &FilePath,
+) -> Option<Arc<Vec<(String, String)>>> {
+    let key = data.to_key(service);
+    if let Some(submodules) = cache.submodules(&key) {
+        return Some(submodules);
+    }
+    let url = service.raw_url(&data.user, &data.repo, &data.commit, ".gitmodules");
+    let submodules = match service.fetch_raw(client, &url).await {
+        Ok(config) => git::submodules(std::str::from_utf8(&config).ok()?)
+            .into_iter()
+            .map(|(path, url)| (path.to_string(), url.to_string()))
+            .collect(),
+        Err(Error::Upstream(StatusCode::NOT_FOUND)) => Vec::new(),
+        Err(_) => return None,
+    };
+    let submodules = Arc::new(submodules);
+    cache.store_submodules(key, Arc::clone(&submodules));
+    Some(submodules)
+}
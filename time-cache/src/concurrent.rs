@@ -0,0 +1,317 @@
+//! Sharded cache, that can be used concurrently through a shared reference.
+
+use crate::{Cache, CacheResult, TtlFn};
+
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+
+/// Number of shards. Each shard is locked independently.
+const SHARDS: usize = 16;
+
+/// Time based cache, that can be shared between threads. Items are distributed over multiple
+/// shards by the hash of their key, so concurrent accesses to different keys rarely contend for
+/// the same lock.
+pub struct ConcurrentCache<K, V> {
+    shards: Box<[RwLock<Cache<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K, V> ConcurrentCache<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new cache.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache: ConcurrentCache<u8, u8> = ConcurrentCache::new(Duration::from_secs(0));
+    /// assert_eq!(CacheResult::Empty, cache.get(&0));
+    /// ```
+    #[must_use]
+    pub fn new(duration: Duration) -> Self {
+        Self::from_shards(|| Cache::new(duration))
+    }
+
+    /// Creates a new cache that holds at most `capacity` items. The capacity is split evenly
+    /// between the shards and each shard evicts its least recently used items on its own, so
+    /// eviction is approximate.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::bounded(Duration::from_secs(100), 16);
+    /// for i in 0..100 {
+    ///     cache.store(i, i);
+    /// }
+    /// let cached = (0..100)
+    ///     .filter(|i| cache.get(i) == CacheResult::Cached(*i))
+    ///     .count();
+    /// assert!(cached <= 16);
+    /// ```
+    #[must_use]
+    pub fn bounded(duration: Duration, capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must not be zero");
+        let capacity = capacity.div_ceil(SHARDS);
+        Self::from_shards(|| Cache::bounded(duration, capacity))
+    }
+
+    fn from_shards(shard: impl Fn() -> Cache<K, V>) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::new(shard())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Derives the time items are stored for from their value, instead of using the duration the
+    /// cache was created with. `store_with_ttl` takes precedence.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(100))
+    ///     .ttl_from_value(|value: &u64| Duration::from_secs(*value));
+    /// cache.store(0, 0);
+    /// cache.store(1, 100);
+    /// assert_eq!(CacheResult::Invalid, cache.get(&0));
+    /// assert_eq!(CacheResult::Cached(100), cache.get(&1));
+    /// ```
+    #[must_use]
+    pub fn ttl_from_value(mut self, ttl: impl Fn(&V) -> Duration + Send + Sync + 'static) -> Self {
+        let ttl: TtlFn<V> = Arc::new(ttl);
+        for shard in &mut self.shards {
+            shard.get_mut().unwrap_or_else(PoisonError::into_inner).ttl = Some(Arc::clone(&ttl));
+        }
+        self
+    }
+
+    /// Keeps expired items for `grace`. Within the grace period, `get` returns them as stale.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache =
+    ///     ConcurrentCache::new(Duration::from_secs(0)).grace_period(Duration::from_secs(100));
+    /// cache.store(0, 1);
+    /// assert_eq!(CacheResult::Stale(1), cache.get(&0));
+    /// ```
+    #[must_use]
+    pub fn grace_period(mut self, grace: Duration) -> Self {
+        for shard in &mut self.shards {
+            shard
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .grace = grace;
+        }
+        self
+    }
+
+    /// Get a copy of an item from the cache. The item can be either valid, stale, invalid or non
+    /// existent. Getting a valid or stale item marks it as recently used.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(0));
+    /// assert_eq!(CacheResult::Empty, cache.get(&0));
+    /// cache.store(0, 1);
+    /// assert_eq!(CacheResult::Invalid, cache.get(&0));
+    /// ```
+    pub fn get(&self, key: &K) -> CacheResult<V>
+    where
+        V: Clone,
+    {
+        match self.read(key).get(key) {
+            CacheResult::Cached(value) => CacheResult::Cached(value.clone()),
+            CacheResult::Stale(value) => CacheResult::Stale(value.clone()),
+            CacheResult::Invalid => CacheResult::Invalid,
+            CacheResult::Empty => CacheResult::Empty,
+        }
+    }
+
+    /// Removes an item from the cache. Returns `true` if the key was present.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::ConcurrentCache;
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(0));
+    /// assert!(!cache.invalidate(&0));
+    /// cache.store(0, 1);
+    /// assert!(cache.invalidate(&0));
+    /// ```
+    pub fn invalidate(&self, key: &K) -> bool {
+        self.write(key).invalidate(key)
+    }
+
+    /// Stores an item in the cache. If the cache is bounded and the item's shard is full, items
+    /// are evicted from that shard.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(100));
+    /// assert_eq!(None, cache.store(0, 1));
+    /// assert_eq!(Some(1), cache.store(0, 2));
+    /// assert_eq!(CacheResult::Cached(2), cache.get(&0));
+    /// ```
+    pub fn store(&self, key: K, value: V) -> Option<V> {
+        self.write(&key).store(key, value)
+    }
+
+    /// Stores an item in the cache for `ttl`, regardless of the cache's duration.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(0));
+    /// cache.store_with_ttl(0, 1, Duration::from_secs(100));
+    /// assert_eq!(CacheResult::Cached(1), cache.get(&0));
+    /// ```
+    pub fn store_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.write(&key).store_with_ttl(key, value, ttl)
+    }
+
+    /// Removes all invalid items from the cache. Shards are cleared one after another, so other
+    /// shards stay accessible.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{CacheResult, ConcurrentCache};
+    ///
+    /// let cache = ConcurrentCache::new(Duration::from_secs(0));
+    /// cache.store(0, 1);
+    /// cache.clear();
+    /// assert_eq!(CacheResult::Empty, cache.get(&0));
+    /// ```
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<Cache<K, V>> {
+        // the hash is reduced before converting it, so it fits on any target
+        let shards = self.shards.len() as u64;
+        let index = usize::try_from(self.hasher.hash_one(key) % shards)
+            .expect("shard index fits into usize");
+        &self.shards[index]
+    }
+
+    // a panic while holding the lock cannot leave a shard in an inconsistent state
+    fn read(&self, key: &K) -> RwLockReadGuard<'_, Cache<K, V>> {
+        self.shard(key)
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self, key: &K) -> RwLockWriteGuard<'_, Cache<K, V>> {
+        self.shard(key)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcurrentCache;
+    use crate::CacheResult;
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    fn valid() {
+        let cache = ConcurrentCache::new(Duration::from_secs(100));
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+        cache.store(0, 1);
+        assert_eq!(CacheResult::Cached(1), cache.get(&0));
+    }
+
+    #[test]
+    fn wait_for_invalidation() {
+        let dur = Duration::from_millis(500);
+        let cache = ConcurrentCache::new(dur);
+        cache.store(0, 1);
+        assert_eq!(CacheResult::Cached(1), cache.get(&0));
+        thread::sleep(dur);
+        assert_eq!(CacheResult::Invalid, cache.get(&0));
+        cache.clear();
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+    }
+
+    #[test]
+    fn stale() {
+        let dur = Duration::from_millis(500);
+        let cache = ConcurrentCache::new(dur).grace_period(dur);
+        cache.store(0, 1);
+        assert_eq!(CacheResult::Cached(1), cache.get(&0));
+        thread::sleep(dur);
+        assert_eq!(CacheResult::Stale(1), cache.get(&0));
+        thread::sleep(dur);
+        assert_eq!(CacheResult::Invalid, cache.get(&0));
+    }
+
+    #[test]
+    fn bounded() {
+        let cache = ConcurrentCache::bounded(Duration::from_secs(100), 32);
+        for i in 0..1000 {
+            cache.store(i, i);
+        }
+        let cached = (0..1000)
+            .filter(|i| cache.get(i) == CacheResult::Cached(*i))
+            .count();
+        assert!(cached > 0);
+        assert!(cached <= 32);
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let cache = Arc::new(ConcurrentCache::new(Duration::from_secs(100)));
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        cache.store(t * 100 + i, i);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for key in 0..800 {
+            assert_eq!(CacheResult::Cached(key % 100), cache.get(&key));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cache capacity must not be zero")]
+    fn zero_capacity() {
+        let _: ConcurrentCache<u8, u8> = ConcurrentCache::bounded(Duration::from_secs(100), 0);
+    }
+}
@@ -1,16 +1,45 @@
 //! Simple cache structure that stores values for a specified time. The cache itself is backed by
-//! a `HashMap`.
+//! a `HashMap`. Optionally, the number of items can be bounded, evicting the least recently used
+//! items. The time an item is stored for can be set per item. Expired items can be kept for a
+//! grace period, in which they are reported as stale.
+//!
+//! `ConcurrentCache` is a sharded variant that can be shared between threads.
+
+mod concurrent;
+
+pub use concurrent::ConcurrentCache;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
+/// Derives the time an item is stored for from its value.
+type TtlFn<V> = Arc<dyn Fn(&V) -> Duration + Send + Sync>;
+
 /// Time based cache, that stores values for a defined time.
 pub struct Cache<K, V> {
-    cache: HashMap<K, CacheEntry<V>>,
+    /// Keys are shared with `recency`, so they don't have to be cloned
+    items: HashMap<Arc<K>, CacheEntry<V>>,
+    /// Time items are stored for, unless set otherwise
     duration: Duration,
+    ttl: Option<TtlFn<V>>,
+    /// Time expired items are still returned as stale
+    grace: Duration,
+    /// Maximum number of items, unbounded if `None`
+    capacity: Option<usize>,
+    /// Keys of bounded caches in the order they were queued for eviction, tagged with the tick
+    /// they were queued at. Items of removed keys are skipped lazily.
+    recency: VecDeque<(u64, Arc<K>)>,
+    /// Tick at which a full cache is swept for invalid items next
+    next_sweep: u64,
+    /// Logical clock to track the last use of items
+    clock: AtomicU64,
 }
 
 impl<K, V> Cache<K, V>
@@ -30,12 +59,103 @@ where
     #[must_use]
     pub fn new(duration: Duration) -> Self {
         Self {
-            cache: HashMap::new(),
+            items: HashMap::new(),
+            duration,
+            ttl: None,
+            grace: Duration::ZERO,
+            capacity: None,
+            recency: VecDeque::new(),
+            next_sweep: 0,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a new cache that holds at most `capacity` items. If the cache is full, storing a
+    /// new item removes the least recently used item. Invalid items are removed once every
+    /// `capacity` stores into a full cache, so eviction takes constant time on average.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{Cache, CacheResult};
+    ///
+    /// let mut cache: Cache<u8, u8> = Cache::bounded(Duration::from_secs(100), 2);
+    /// cache.store(0, 0);
+    /// cache.store(1, 1);
+    /// assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+    /// cache.store(2, 2);
+    /// assert_eq!(CacheResult::Empty, cache.get(&1));
+    /// assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+    /// ```
+    #[must_use]
+    pub fn bounded(duration: Duration, capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must not be zero");
+        Self {
+            items: HashMap::with_capacity(capacity),
             duration,
+            ttl: None,
+            grace: Duration::ZERO,
+            capacity: Some(capacity),
+            recency: VecDeque::with_capacity(capacity),
+            next_sweep: 0,
+            clock: AtomicU64::new(0),
         }
     }
 
-    /// Get an item from the cache. The item can be either valid, invalid or non existent.
+    /// Derives the time items are stored for from their value, instead of using the duration the
+    /// cache was created with. `store_with_ttl` takes precedence.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{Cache, CacheResult};
+    ///
+    /// // negative lookups are cached shortly
+    /// let mut cache = Cache::new(Duration::from_secs(100))
+    ///     .ttl_from_value(|value: &Option<u8>| {
+    ///         if value.is_some() {
+    ///             Duration::from_secs(100)
+    ///         } else {
+    ///             Duration::from_secs(0)
+    ///         }
+    ///     });
+    /// cache.store(0, Some(0));
+    /// cache.store(1, None);
+    /// assert_eq!(CacheResult::Cached(&Some(0)), cache.get(&0));
+    /// assert_eq!(CacheResult::Invalid, cache.get(&1));
+    /// ```
+    #[must_use]
+    pub fn ttl_from_value(mut self, ttl: impl Fn(&V) -> Duration + Send + Sync + 'static) -> Self {
+        self.ttl = Some(Arc::new(ttl));
+        self
+    }
+
+    /// Keeps expired items for `grace`. Within the grace period, `get` returns them as stale,
+    /// e.g. to serve them while a fresh value is fetched.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{Cache, CacheResult};
+    ///
+    /// let mut cache: Cache<u8, u8> =
+    ///     Cache::new(Duration::from_secs(0)).grace_period(Duration::from_secs(100));
+    /// cache.store(0, 1);
+    /// assert_eq!(CacheResult::Stale(&1), cache.get(&0));
+    /// cache.clear();
+    /// assert_eq!(CacheResult::Stale(&1), cache.get(&0));
+    /// ```
+    #[must_use]
+    pub fn grace_period(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// Get an item from the cache. The item can be either valid, stale, invalid or non existent.
+    /// Getting a valid or stale item marks it as recently used.
     ///
     /// # Example
     /// ```
@@ -50,11 +170,16 @@ where
     /// assert_eq!(CacheResult::Invalid, cache.get(&key));
     /// ```
     pub fn get(&self, key: &K) -> CacheResult<&V> {
-        if let Some(entry) = self.cache.get(key) {
-            if Self::is_valid(Instant::now(), entry) {
-                CacheResult::Cached(&entry.1)
+        if let Some(entry) = self.items.get(key) {
+            let now = Instant::now();
+            if !Self::is_usable(now, entry, self.grace) {
+                return CacheResult::Invalid;
+            }
+            entry.last_used.store(self.tick(), Ordering::Relaxed);
+            if Self::is_valid(now, entry) {
+                CacheResult::Cached(&entry.value)
             } else {
-                CacheResult::Invalid
+                CacheResult::Stale(&entry.value)
             }
         } else {
             CacheResult::Empty
@@ -76,10 +201,10 @@ where
     /// assert!(cache.invalidate(&key));
     /// ```
     pub fn invalidate(&mut self, key: &K) -> bool {
-        self.cache.remove(key).is_some()
+        self.items.remove(key).is_some()
     }
 
-    /// Stores an item in the cache.
+    /// Stores an item in the cache. If the cache is bounded and full, items are evicted.
     ///
     /// # Example
     /// ```
@@ -98,12 +223,49 @@ where
     /// assert_eq!(CacheResult::Invalid, cache.get(&key));
     /// ```
     pub fn store(&mut self, key: K, value: V) -> Option<V> {
-        self.cache
-            .insert(key, CacheEntry::new(value, self.duration))
-            .map(|old| old.1)
+        let ttl = self.ttl.as_ref().map_or(self.duration, |ttl| ttl(&value));
+        self.store_with_ttl(key, value, ttl)
+    }
+
+    /// Stores an item in the cache for `ttl`, regardless of the cache's duration.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use time_cache::{Cache, CacheResult};
+    ///
+    /// let mut cache: Cache<u8, u8> = Cache::new(Duration::from_secs(0));
+    ///
+    /// cache.store(0, 0);
+    /// cache.store_with_ttl(1, 1, Duration::from_secs(100));
+    /// assert_eq!(CacheResult::Invalid, cache.get(&0));
+    /// assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+    /// ```
+    pub fn store_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let tick = self.tick();
+        let Some(capacity) = self.capacity else {
+            let entry = CacheEntry::new(value, ttl, tick, tick);
+            return self.items.insert(Arc::new(key), entry).map(|old| old.value);
+        };
+        if let Some(old) = self.items.get_mut(&key) {
+            // the key keeps its place in the queue
+            let entry = CacheEntry::new(value, ttl, tick, old.queued);
+            return Some(std::mem::replace(old, entry).value);
+        }
+        if self.items.len() >= capacity {
+            self.evict(tick, capacity);
+        } else if self.recency.len() >= 2 * capacity {
+            // drop the items of removed keys
+            self.compact();
+        }
+        let key = Arc::new(key);
+        self.recency.push_back((tick, Arc::clone(&key)));
+        self.items
+            .insert(key, CacheEntry::new(value, ttl, tick, tick));
+        None
     }
 
-    /// Removes all invalid items from the cache.
+    /// Removes all invalid items from the cache. Stale items are kept.
     ///
     /// # Example
     /// ```
@@ -123,11 +285,59 @@ where
     /// ```
     pub fn clear(&mut self) {
         let now = Instant::now();
-        self.cache.retain(|_, v| Self::is_valid(now, v));
+        let grace = self.grace;
+        self.items.retain(|_, v| Self::is_usable(now, v, grace));
+    }
+
+    /// Makes room for a new item by removing the least recently used one. Every `capacity` ticks,
+    /// invalid items are removed first.
+    fn evict(&mut self, tick: u64, capacity: usize) {
+        if tick >= self.next_sweep {
+            self.next_sweep = tick + capacity as u64;
+            self.clear();
+            self.compact();
+            if self.items.len() < capacity {
+                return;
+            }
+        }
+        while let Some((queued, key)) = self.recency.pop_front() {
+            let Some(entry) = self.items.get_mut(&key) else {
+                continue;
+            };
+            if entry.queued != queued {
+                // the key was removed and stored again, it has been queued a second time
+                continue;
+            }
+            let last_used = *entry.last_used.get_mut();
+            if last_used == queued {
+                self.items.remove(&key);
+                return;
+            }
+            // used since it was queued, so it is queued again
+            entry.queued = last_used;
+            self.recency.push_back((last_used, key));
+        }
+    }
+
+    /// Removes the queued items of keys that are no longer cached.
+    fn compact(&mut self) {
+        let items = &self.items;
+        self.recency
+            .retain(|(queued, key)| items.get(key).is_some_and(|entry| entry.queued == *queued));
+    }
+
+    /// Advances the logical clock.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
     }
 
     fn is_valid(when: Instant, entry: &CacheEntry<V>) -> bool {
-        entry.0 >= when
+        entry.valid_until >= when
+    }
+
+    /// Checks if an item is valid or stale.
+    fn is_usable(when: Instant, entry: &CacheEntry<V>, grace: Duration) -> bool {
+        entry.valid_until + grace >= when
     }
 }
 
@@ -136,17 +346,31 @@ where
 pub enum CacheResult<T> {
     /// Item is cached and still valid
     Cached(T),
+    /// Item is cached and expired, but still within the grace period
+    Stale(T),
     /// Item is cached but invalid
     Invalid,
     /// Item is not in the cache
     Empty,
 }
 
-struct CacheEntry<T>(Instant, T);
+struct CacheEntry<T> {
+    valid_until: Instant,
+    /// Tick of the logical clock when the item was last used
+    last_used: AtomicU64,
+    /// Tick the item was queued for eviction at
+    queued: u64,
+    value: T,
+}
 
 impl<T> CacheEntry<T> {
-    fn new(value: T, duration: Duration) -> Self {
-        CacheEntry(Instant::now() + duration, value)
+    fn new(value: T, duration: Duration, tick: u64, queued: u64) -> Self {
+        CacheEntry {
+            valid_until: Instant::now() + duration,
+            last_used: AtomicU64::new(tick),
+            queued,
+            value,
+        }
     }
 }
 
@@ -228,4 +452,135 @@ mod tests {
         cache.clear();
         assert_eq!(CacheResult::Empty, cache.get(&key));
     }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dur = Duration::from_secs(100);
+        let mut cache = Cache::bounded(dur, 3);
+        cache.store(0, 0);
+        cache.store(1, 1);
+        cache.store(2, 2);
+        assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+        assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+        cache.store(3, 3);
+        assert_eq!(CacheResult::Empty, cache.get(&2));
+        assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+        assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+        assert_eq!(CacheResult::Cached(&3), cache.get(&3));
+    }
+
+    #[test]
+    fn evict_invalid_first() {
+        let mut cache = Cache::bounded(Duration::from_secs(0), 2);
+        cache.store(0, 0);
+        cache.store(1, 1);
+        cache.store(2, 2);
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+        assert_eq!(CacheResult::Empty, cache.get(&1));
+        assert_eq!(CacheResult::Invalid, cache.get(&2));
+    }
+
+    #[test]
+    fn replace_without_eviction() {
+        let dur = Duration::from_secs(100);
+        let mut cache = Cache::bounded(dur, 2);
+        cache.store(0, 0);
+        cache.store(1, 1);
+        assert_eq!(Some(1), cache.store(1, 2));
+        assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+        assert_eq!(CacheResult::Cached(&2), cache.get(&1));
+    }
+
+    #[test]
+    fn store_with_ttl() {
+        let dur = Duration::from_millis(500);
+        let mut cache = Cache::new(Duration::from_secs(100));
+        cache.store_with_ttl(0, 0, dur);
+        cache.store(1, 1);
+        assert_eq!(CacheResult::Cached(&0), cache.get(&0));
+        std::thread::sleep(dur);
+        assert_eq!(CacheResult::Invalid, cache.get(&0));
+        assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+    }
+
+    #[test]
+    fn ttl_from_value() {
+        let mut cache = Cache::new(Duration::from_secs(100))
+            .ttl_from_value(|value: &u64| Duration::from_secs(*value));
+        cache.store(0, 0);
+        cache.store(1, 100);
+        cache.store_with_ttl(2, 0, Duration::from_secs(100));
+        assert_eq!(CacheResult::Invalid, cache.get(&0));
+        assert_eq!(CacheResult::Cached(&100), cache.get(&1));
+        assert_eq!(CacheResult::Cached(&0), cache.get(&2));
+    }
+
+    #[test]
+    fn stale_within_grace_period() {
+        let dur = Duration::from_millis(500);
+        let mut cache = Cache::new(dur).grace_period(dur);
+        cache.store(0, 1);
+        assert_eq!(CacheResult::Cached(&1), cache.get(&0));
+        std::thread::sleep(dur);
+        assert_eq!(CacheResult::Stale(&1), cache.get(&0));
+        cache.clear();
+        assert_eq!(CacheResult::Stale(&1), cache.get(&0));
+        std::thread::sleep(dur);
+        assert_eq!(CacheResult::Invalid, cache.get(&0));
+        cache.clear();
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+    }
+
+    #[test]
+    fn evict_stale_last() {
+        let mut cache =
+            Cache::bounded(Duration::from_secs(0), 1).grace_period(Duration::from_secs(100));
+        cache.store(0, 0);
+        cache.store(1, 1);
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+        assert_eq!(CacheResult::Stale(&1), cache.get(&1));
+    }
+
+    #[test]
+    fn evict_restored_key_by_last_use() {
+        let mut cache = Cache::bounded(Duration::from_secs(100), 2);
+        cache.store(0, 0);
+        cache.store(1, 1);
+        assert!(cache.invalidate(&0));
+        cache.store(0, 0);
+        assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+        cache.store(2, 2);
+        assert_eq!(CacheResult::Empty, cache.get(&0));
+        assert_eq!(CacheResult::Cached(&1), cache.get(&1));
+        assert_eq!(CacheResult::Cached(&2), cache.get(&2));
+    }
+
+    #[test]
+    fn queue_stays_bounded() {
+        let mut cache = Cache::bounded(Duration::from_secs(100), 4);
+        for i in 0..100 {
+            cache.store(i % 2, i);
+            cache.invalidate(&(i % 2));
+            cache.store(i, i);
+        }
+        assert!(cache.recency.len() <= 2 * 4 + 1);
+    }
+
+    #[test]
+    fn bounded_without_clone() {
+        #[derive(PartialEq, Eq, Hash)]
+        struct Key(u8);
+
+        let mut cache = Cache::bounded(Duration::from_secs(100), 1);
+        cache.store(Key(0), 0);
+        cache.store(Key(1), 1);
+        assert_eq!(CacheResult::Empty, cache.get(&Key(0)));
+        assert_eq!(CacheResult::Cached(&1), cache.get(&Key(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "cache capacity must not be zero")]
+    fn zero_capacity() {
+        let _: Cache<u8, u8> = Cache::bounded(Duration::from_secs(100), 0);
+    }
 }